@@ -0,0 +1,220 @@
+use crate::actions::{Actions, TickActionsSystem};
+use crate::combat::Invulnerable;
+use crate::player::{is_wall, move_and_slide, MovementSettings, Player, PlayerMotion};
+use crate::timestep::{FixedTime, FixedUpdateAppExt};
+use crate::GameState;
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+pub struct AbilityPlugin;
+
+/// This plugin gives the player active abilities like the dash
+/// An ability is requested through [Actions], announced with an [AbilityUsedEvent] and then goes on cooldown
+/// A new ability needs an [AbilityKind], a request in [Actions] and a system reacting to its event
+impl Plugin for AbilityPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<AbilityUsedEvent>().add_fixed_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .label(AbilitySystem)
+                .after(TickActionsSystem)
+                .with_system(use_abilities)
+                .with_system(start_dash.after(use_abilities))
+                .with_system(dash.after(start_dash)),
+        );
+    }
+}
+
+/// Label of the systems triggering and performing abilities
+#[derive(Clone, Debug, PartialEq, Eq, Hash, SystemLabel)]
+pub struct AbilitySystem;
+
+/// Distance covered by a dash unless a wall is in the way
+const DASH_DISTANCE: f32 = 150.;
+/// Seconds a dash takes
+const DASH_DURATION: f32 = 0.15;
+/// Seconds the player can't be hurt after starting a dash
+const DASH_INVULNERABILITY: f32 = 0.3;
+const DASH_COOLDOWN: f32 = 1.2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AbilityKind {
+    Dash,
+}
+
+impl AbilityKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AbilityKind::Dash => "Dash",
+        }
+    }
+
+    /// Seconds after using the ability until it can be used again
+    pub fn cooldown(&self) -> f32 {
+        match self {
+            AbilityKind::Dash => DASH_COOLDOWN,
+        }
+    }
+
+    /// Whether the ability was requested since the last tick, the request is consumed
+    fn take_request(&self, actions: &mut Actions) -> bool {
+        match self {
+            AbilityKind::Dash => std::mem::take(&mut actions.dash_pressed),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Ability {
+    pub kind: AbilityKind,
+    cooldown: Timer,
+}
+
+impl Ability {
+    /// A new ability is ready right away
+    pub fn new(kind: AbilityKind) -> Self {
+        let mut cooldown = Timer::from_seconds(kind.cooldown(), false);
+        cooldown.tick(cooldown.duration());
+        Ability { kind, cooldown }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.cooldown.finished()
+    }
+
+    /// Seconds until the ability can be used again
+    pub fn remaining_cooldown(&self) -> f32 {
+        (self.cooldown.duration() - self.cooldown.elapsed()).as_secs_f32()
+    }
+
+    /// How far the cooldown has run, from 0 right after using the ability to 1 when it is ready
+    pub fn cooldown_progress(&self) -> f32 {
+        self.cooldown.percent()
+    }
+}
+
+/// The abilities of an entity, in the order they are shown in the HUD
+#[derive(Component, Debug)]
+pub struct Abilities(Vec<Ability>);
+
+impl Abilities {
+    pub fn new(kinds: &[AbilityKind]) -> Self {
+        Abilities(kinds.iter().copied().map(Ability::new).collect())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Ability> {
+        self.0.iter()
+    }
+
+    pub fn get(&self, kind: AbilityKind) -> Option<&Ability> {
+        self.0.iter().find(|ability| ability.kind == kind)
+    }
+}
+
+/// Sent when an entity uses one of its abilities
+pub struct AbilityUsedEvent {
+    pub user: Entity,
+    pub kind: AbilityKind,
+}
+
+/// A dash in progress, the dashing entity isn't moved by its usual movement until it ends
+#[derive(Component, Debug)]
+pub struct Dashing {
+    direction: Vec2,
+    timer: Timer,
+}
+
+fn use_abilities(
+    time: Res<FixedTime>,
+    mut actions: ResMut<Actions>,
+    mut used_events: EventWriter<AbilityUsedEvent>,
+    mut query: Query<(Entity, &mut Abilities), With<Player>>,
+) {
+    for (entity, mut abilities) in query.iter_mut() {
+        for ability in abilities.0.iter_mut() {
+            ability.cooldown.tick(time.delta());
+            // requests during the cooldown are dropped, not queued
+            if ability.kind.take_request(&mut actions) && ability.is_ready() {
+                ability.cooldown.reset();
+                used_events.send(AbilityUsedEvent {
+                    user: entity,
+                    kind: ability.kind,
+                });
+            }
+        }
+    }
+}
+
+fn start_dash(
+    mut commands: Commands,
+    actions: Res<Actions>,
+    mut used_events: EventReader<AbilityUsedEvent>,
+    query: Query<(&Transform, Option<&Invulnerable>)>,
+) {
+    for event in used_events.iter() {
+        if event.kind != AbilityKind::Dash {
+            continue;
+        }
+        let (transform, invulnerable) = match query.get(event.user) {
+            Ok(user) => user,
+            Err(_) => continue,
+        };
+        // dash where the player is going, standing still where they aim or face
+        let direction = actions
+            .player_movement
+            .and_then(Vec2::try_normalize)
+            .or(actions.aim_direction)
+            .unwrap_or_else(|| (transform.rotation * Vec3::Y).truncate());
+
+        let mut user = commands.entity(event.user);
+        user.insert(Dashing {
+            direction,
+            timer: Timer::from_seconds(DASH_DURATION, false),
+        });
+        // a longer invulnerability, e.g. after being hit, isn't cut short
+        let protected = invulnerable.map_or(false, |invulnerable| {
+            invulnerable.0.duration().as_secs_f32() - invulnerable.0.elapsed_secs()
+                > DASH_INVULNERABILITY
+        });
+        if !protected {
+            user.insert(Invulnerable::for_seconds(DASH_INVULNERABILITY));
+        }
+    }
+}
+
+fn dash(
+    mut commands: Commands,
+    time: Res<FixedTime>,
+    settings: Res<MovementSettings>,
+    rapier_context: Res<RapierContext>,
+    body_query: Query<Option<&RigidBody>>,
+    mut query: Query<(
+        Entity,
+        &mut Transform,
+        &mut Dashing,
+        &Collider,
+        Option<&mut PlayerMotion>,
+    )>,
+) {
+    let is_wall = |entity| is_wall(&body_query, entity);
+    let speed = DASH_DISTANCE / DASH_DURATION;
+
+    for (entity, mut transform, mut dashing, collider, motion) in query.iter_mut() {
+        dashing.timer.tick(time.delta());
+        let slide = move_and_slide(
+            &rapier_context,
+            collider,
+            transform.translation.truncate(),
+            dashing.direction * speed * time.delta_seconds(),
+            &is_wall,
+        );
+        transform.translation = slide.position.extend(transform.translation.z);
+
+        // a wall ends the dash early
+        if slide.normal.is_some() || dashing.timer.finished() {
+            commands.entity(entity).remove::<Dashing>();
+            if let Some(mut motion) = motion {
+                motion.velocity = dashing.direction * settings.max_speed;
+            }
+        }
+    }
+}
@@ -1,72 +1,230 @@
+use crate::bindings::{ControlInput, GameControl, KeyBindings};
+use crate::camera::MainCamera;
+use crate::loading::BindingAssets;
+use crate::player::Player;
+use crate::settings::Settings;
 use crate::GameState;
+use bevy::input::mouse::MouseWheel;
 use bevy::prelude::*;
+use bevy::render::camera::RenderTarget;
 
 pub struct ActionsPlugin;
 
 // This plugin listens for keyboard input and converts the input into Actions
 // Actions can then be used as a resource in other systems to act on the player input.
+// Which physical inputs trigger which action is looked up in the KeyBindings resource.
 impl Plugin for ActionsPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<Actions>().add_system_set(
-            SystemSet::on_update(GameState::Playing).with_system(set_movement_actions),
-        );
+        app.init_resource::<Actions>()
+            .init_resource::<ActionSource>()
+            .init_resource::<KeyBindings>()
+            .init_resource::<ActiveGamepad>()
+            .init_resource::<StickDeadzones>()
+            .add_system(track_active_gamepad)
+            .add_system_set(
+                SystemSet::on_exit(GameState::Loading).with_system(insert_loaded_bindings),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .label(ActionsSystem)
+                    .with_system(set_movement_actions)
+                    .with_system(set_aim_actions)
+                    .with_system(set_weapon_actions),
+            );
+    }
+}
+
+/// Label of the systems filling [Actions], systems reading them should run after it
+#[derive(Clone, Debug, PartialEq, Eq, Hash, SystemLabel)]
+pub struct ActionsSystem;
+
+/// Label of the systems recording or replaying [Actions] at the start of a fixed tick
+/// Systems reading them in the fixed tick should run after it
+#[derive(Clone, Debug, PartialEq, Eq, Hash, SystemLabel)]
+pub struct TickActionsSystem;
+
+/// Where [Actions] come from, unless they come from input the input systems leave them alone
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ActionSource {
+    Input,
+    Replay,
+    /// Set directly by other code, e.g. a test driving the headless app
+    Scripted,
+}
+
+impl Default for ActionSource {
+    fn default() -> Self {
+        ActionSource::Input
     }
 }
 
 #[derive(Default)]
 pub struct Actions {
     pub player_movement: Option<Vec2>,
+    /// The world position the player is aiming at
+    pub aim_target: Option<Vec2>,
+    /// Normalized direction from the player towards `aim_target`
+    pub aim_direction: Option<Vec2>,
+    pub aim_device: AimDevice,
     pub grabbed_mouse: (bool, bool),
     pub trigger_pressed: bool,
+    /// Set when a reload was requested, until the next fixed tick handles it
+    pub reload_pressed: bool,
+    /// Set when a dash was requested, until the next fixed tick handles it
+    pub dash_pressed: bool,
+    /// Set when the player asked for another weapon, until the next fixed tick handles it
+    pub weapon_selection: Option<WeaponSelection>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WeaponSelection {
+    /// A fixed inventory slot, counting from 0
+    Slot(usize),
+    Next,
+    Previous,
+}
+
+/// The device the player last aimed with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AimDevice {
+    Mouse,
+    Gamepad,
+}
+
+impl Default for AimDevice {
+    fn default() -> Self {
+        AimDevice::Mouse
+    }
+}
+
+/// The gamepad whose sticks drive the player
+/// Set to the last gamepad that was connected or used, and cleared once no gamepad is left
+#[derive(Default)]
+pub struct ActiveGamepad(pub Option<Gamepad>);
+
+/// Radial deadzones of the analog sticks as a fraction of their full deflection
+pub struct StickDeadzones {
+    pub movement: f32,
+    pub aim: f32,
+}
+
+impl Default for StickDeadzones {
+    fn default() -> Self {
+        StickDeadzones {
+            movement: 0.2,
+            aim: 0.3,
+        }
+    }
+}
+
+/// Bindings the player changed in the settings win over the defaults
+fn insert_loaded_bindings(
+    mut bindings: ResMut<KeyBindings>,
+    settings: Option<Res<Settings>>,
+    binding_assets: Res<BindingAssets>,
+    loaded_bindings: Res<Assets<KeyBindings>>,
+) {
+    if let Some(custom) = settings
+        .as_ref()
+        .and_then(|settings| settings.key_bindings.as_ref())
+    {
+        *bindings = custom.clone();
+    } else if let Some(loaded) = loaded_bindings.get(&binding_assets.key_bindings) {
+        *bindings = loaded.clone();
+    }
+}
+
+fn track_active_gamepad(
+    mut active_gamepad: ResMut<ActiveGamepad>,
+    gamepads: Res<Gamepads>,
+    deadzones: Res<StickDeadzones>,
+    mut gamepad_events: EventReader<GamepadEvent>,
+) {
+    for GamepadEvent(gamepad, event_type) in gamepad_events.iter() {
+        match event_type {
+            GamepadEventType::Connected => {
+                if active_gamepad.0.is_none() {
+                    active_gamepad.0 = Some(*gamepad);
+                }
+            }
+            GamepadEventType::Disconnected => {
+                if active_gamepad.0 == Some(*gamepad) {
+                    active_gamepad.0 = gamepads.iter().find(|other| *other != gamepad).copied();
+                }
+            }
+            GamepadEventType::ButtonChanged(_, value) => {
+                if *value > 0. {
+                    active_gamepad.0 = Some(*gamepad);
+                }
+            }
+            // a resting stick drifts a little, only a deliberate push takes over
+            GamepadEventType::AxisChanged(axis, value) => {
+                let deadzone = match axis {
+                    GamepadAxisType::RightStickX | GamepadAxisType::RightStickY => deadzones.aim,
+                    _ => deadzones.movement,
+                };
+                if value.abs() > deadzone {
+                    active_gamepad.0 = Some(*gamepad);
+                }
+            }
+        }
+    }
 }
 
 fn set_movement_actions(
     mut actions: ResMut<Actions>,
-    keyboard_input: Res<Input<KeyCode>>,
-    buttons: Res<Input<MouseButton>>,
+    source: Res<ActionSource>,
+    bindings: Res<KeyBindings>,
+    input: ControlInput,
+    active_gamepad: Res<ActiveGamepad>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    deadzones: Res<StickDeadzones>,
 ) {
-    if GameControl::Up.just_released(&keyboard_input, &buttons)
-        || GameControl::Up.pressed(&keyboard_input, &buttons)
-        || GameControl::Left.just_released(&keyboard_input, &buttons)
-        || GameControl::Left.pressed(&keyboard_input, &buttons)
-        || GameControl::Down.just_released(&keyboard_input, &buttons)
-        || GameControl::Down.pressed(&keyboard_input, &buttons)
-        || GameControl::Right.just_released(&keyboard_input, &buttons)
-        || GameControl::Right.pressed(&keyboard_input, &buttons)
+    if *source != ActionSource::Input {
+        return;
+    }
+    if bindings.just_released(GameControl::Up, &input)
+        || bindings.pressed(GameControl::Up, &input)
+        || bindings.just_released(GameControl::Left, &input)
+        || bindings.pressed(GameControl::Left, &input)
+        || bindings.just_released(GameControl::Down, &input)
+        || bindings.pressed(GameControl::Down, &input)
+        || bindings.just_released(GameControl::Right, &input)
+        || bindings.pressed(GameControl::Right, &input)
     {
         let mut player_movement = Vec2::ZERO;
 
-        if GameControl::Up.just_released(&keyboard_input, &buttons)
-            || GameControl::Down.just_released(&keyboard_input, &buttons)
+        if bindings.just_released(GameControl::Up, &input)
+            || bindings.just_released(GameControl::Down, &input)
         {
-            if GameControl::Up.pressed(&keyboard_input, &buttons) {
+            if bindings.pressed(GameControl::Up, &input) {
                 player_movement.y = 1.;
-            } else if GameControl::Down.pressed(&keyboard_input, &buttons) {
+            } else if bindings.pressed(GameControl::Down, &input) {
                 player_movement.y = -1.;
             } else {
                 player_movement.y = 0.;
             }
-        } else if GameControl::Up.just_pressed(&keyboard_input, &buttons) {
+        } else if bindings.just_pressed(GameControl::Up, &input) {
             player_movement.y = 1.;
-        } else if GameControl::Down.just_pressed(&keyboard_input, &buttons) {
+        } else if bindings.just_pressed(GameControl::Down, &input) {
             player_movement.y = -1.;
         } else {
             player_movement.y = actions.player_movement.unwrap_or(Vec2::ZERO).y;
         }
 
-        if GameControl::Right.just_released(&keyboard_input, &buttons)
-            || GameControl::Left.just_released(&keyboard_input, &buttons)
+        if bindings.just_released(GameControl::Right, &input)
+            || bindings.just_released(GameControl::Left, &input)
         {
-            if GameControl::Right.pressed(&keyboard_input, &buttons) {
+            if bindings.pressed(GameControl::Right, &input) {
                 player_movement.x = 1.;
-            } else if GameControl::Left.pressed(&keyboard_input, &buttons) {
+            } else if bindings.pressed(GameControl::Left, &input) {
                 player_movement.x = -1.;
             } else {
                 player_movement.x = 0.;
             }
-        } else if GameControl::Right.just_pressed(&keyboard_input, &buttons) {
+        } else if bindings.just_pressed(GameControl::Right, &input) {
             player_movement.x = 1.;
-        } else if GameControl::Left.just_pressed(&keyboard_input, &buttons) {
+        } else if bindings.just_pressed(GameControl::Left, &input) {
             player_movement.x = -1.;
         } else {
             player_movement.x = actions.player_movement.unwrap_or(Vec2::ZERO).x;
@@ -77,14 +235,22 @@ fn set_movement_actions(
             actions.player_movement = Some(player_movement);
         }
     } else {
-        actions.player_movement = None;
+        actions.player_movement = active_gamepad.0.and_then(|gamepad| {
+            let left_stick = stick(
+                &gamepad_axes,
+                gamepad,
+                GamepadAxisType::LeftStickX,
+                GamepadAxisType::LeftStickY,
+            );
+            apply_deadzone(left_stick, deadzones.movement)
+        });
     }
 
-    if GameControl::MouseGrab.just_released(&keyboard_input, &buttons) {
+    if bindings.just_released(GameControl::MouseGrab, &input) {
         actions.grabbed_mouse.0 = false;
     }
 
-    if GameControl::MouseGrab.just_pressed(&keyboard_input, &buttons) {
+    if bindings.just_pressed(GameControl::MouseGrab, &input) {
         actions.grabbed_mouse.0 = true;
         if actions.grabbed_mouse.1 {
             actions.grabbed_mouse.1 = false;
@@ -93,96 +259,198 @@ fn set_movement_actions(
         }
     }
 
-    if GameControl::Trigger.pressed(&keyboard_input, &buttons) {
+    if bindings.pressed(GameControl::Trigger, &input) {
         actions.trigger_pressed = true;
     } else {
         actions.trigger_pressed = false;
     }
+
+    if bindings.just_pressed(GameControl::Reload, &input) {
+        actions.reload_pressed = true;
+    }
+
+    if bindings.just_pressed(GameControl::Dash, &input) {
+        actions.dash_pressed = true;
+    }
 }
 
-enum GameControl {
-    Up,
-    Down,
-    Left,
-    Right,
-    MouseGrab,
-    Trigger,
+fn set_weapon_actions(
+    mut actions: ResMut<Actions>,
+    source: Res<ActionSource>,
+    bindings: Res<KeyBindings>,
+    input: ControlInput,
+    mut mouse_wheel: EventReader<MouseWheel>,
+) {
+    let scrolled: f32 = mouse_wheel.iter().map(|wheel| wheel.y).sum();
+    let slots = [
+        GameControl::Weapon1,
+        GameControl::Weapon2,
+        GameControl::Weapon3,
+        GameControl::Weapon4,
+        GameControl::Weapon5,
+    ];
+
+    if *source != ActionSource::Input {
+        return;
+    }
+
+    let selection = if let Some(slot) = slots
+        .iter()
+        .position(|control| bindings.just_pressed(*control, &input))
+    {
+        Some(WeaponSelection::Slot(slot))
+    } else if bindings.just_pressed(GameControl::NextWeapon, &input) || scrolled < 0. {
+        Some(WeaponSelection::Next)
+    } else if bindings.just_pressed(GameControl::PreviousWeapon, &input) || scrolled > 0. {
+        Some(WeaponSelection::Previous)
+    } else {
+        None
+    };
+    if selection.is_some() {
+        actions.weapon_selection = selection;
+    }
 }
 
-impl GameControl {
-    fn just_released(
-        &self,
-        keyboard_input: &Res<Input<KeyCode>>,
-        buttons: &Res<Input<MouseButton>>,
-    ) -> bool {
-        match self {
-            GameControl::Up => {
-                keyboard_input.just_released(KeyCode::W)
-                    || keyboard_input.just_released(KeyCode::Up)
-            }
-            GameControl::Down => {
-                keyboard_input.just_released(KeyCode::S)
-                    || keyboard_input.just_released(KeyCode::Down)
-            }
-            GameControl::Left => {
-                keyboard_input.just_released(KeyCode::A)
-                    || keyboard_input.just_released(KeyCode::Left)
-            }
-            GameControl::Right => {
-                keyboard_input.just_released(KeyCode::D)
-                    || keyboard_input.just_released(KeyCode::Right)
-            }
-            GameControl::MouseGrab => keyboard_input.just_released(KeyCode::Escape),
-            GameControl::Trigger => buttons.just_released(MouseButton::Left),
-        }
+fn set_aim_actions(
+    mut actions: ResMut<Actions>,
+    source: Res<ActionSource>,
+    mut cursor_moved: EventReader<CursorMoved>,
+    windows: Res<Windows>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    player_query: Query<&Transform, With<Player>>,
+    active_gamepad: Res<ActiveGamepad>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    deadzones: Res<StickDeadzones>,
+) {
+    if *source != ActionSource::Input {
+        return;
+    }
+    if cursor_moved.iter().last().is_some() {
+        actions.aim_device = AimDevice::Mouse;
     }
 
-    fn pressed(
-        &self,
-        keyboard_input: &Res<Input<KeyCode>>,
-        buttons: &Res<Input<MouseButton>>,
-    ) -> bool {
-        match self {
-            GameControl::Up => {
-                keyboard_input.pressed(KeyCode::W) || keyboard_input.pressed(KeyCode::Up)
-            }
-            GameControl::Down => {
-                keyboard_input.pressed(KeyCode::S) || keyboard_input.pressed(KeyCode::Down)
-            }
-            GameControl::Left => {
-                keyboard_input.pressed(KeyCode::A) || keyboard_input.pressed(KeyCode::Left)
-            }
-            GameControl::Right => {
-                keyboard_input.pressed(KeyCode::D) || keyboard_input.pressed(KeyCode::Right)
-            }
-            GameControl::MouseGrab => keyboard_input.pressed(KeyCode::Escape),
-            GameControl::Trigger => buttons.pressed(MouseButton::Left),
+    let right_stick = match active_gamepad.0 {
+        Some(gamepad) => stick(
+            &gamepad_axes,
+            gamepad,
+            GamepadAxisType::RightStickX,
+            GamepadAxisType::RightStickY,
+        ),
+        None => {
+            actions.aim_device = AimDevice::Mouse;
+            Vec2::ZERO
         }
+    };
+    let stick_aim = apply_deadzone(right_stick, deadzones.aim);
+    if stick_aim.is_some() {
+        actions.aim_device = AimDevice::Gamepad;
     }
 
-    fn just_pressed(
-        &self,
-        keyboard_input: &Res<Input<KeyCode>>,
-        buttons: &Res<Input<MouseButton>>,
-    ) -> bool {
-        match self {
-            GameControl::Up => {
-                keyboard_input.just_pressed(KeyCode::W) || keyboard_input.just_pressed(KeyCode::Up)
-            }
-            GameControl::Down => {
-                keyboard_input.just_pressed(KeyCode::S)
-                    || keyboard_input.just_pressed(KeyCode::Down)
-            }
-            GameControl::Left => {
-                keyboard_input.just_pressed(KeyCode::A)
-                    || keyboard_input.just_pressed(KeyCode::Left)
+    let player_position = match player_query.get_single() {
+        Ok(transform) => transform.translation.truncate(),
+        Err(_) => return,
+    };
+
+    match actions.aim_device {
+        AimDevice::Mouse => {
+            // outside of the window the last known target is kept
+            if let Some(target) = camera_query
+                .get_single()
+                .ok()
+                .and_then(|(camera, transform)| cursor_to_world(&windows, camera, transform))
+            {
+                actions.aim_target = Some(target);
             }
-            GameControl::Right => {
-                keyboard_input.just_pressed(KeyCode::D)
-                    || keyboard_input.just_pressed(KeyCode::Right)
+        }
+        AimDevice::Gamepad => {
+            // releasing the stick keeps the last direction, so the player doesn't snap back
+            if let Some(aim) = stick_aim {
+                actions.aim_target = Some(player_position + aim.normalize() * GAMEPAD_AIM_DISTANCE);
             }
-            GameControl::MouseGrab => keyboard_input.just_pressed(KeyCode::Escape),
-            GameControl::Trigger => buttons.just_pressed(MouseButton::Left),
         }
     }
+
+    if let Some(direction) = actions
+        .aim_target
+        .and_then(|target| (target - player_position).try_normalize())
+    {
+        actions.aim_direction = Some(direction);
+    }
+}
+
+/// How far in front of the player the aim target is placed when aiming with a stick
+const GAMEPAD_AIM_DISTANCE: f32 = 200.;
+
+/// Projects the cursor through the camera into the world, taking the camera's
+/// position, rotation and zoom as well as the current window size into account
+fn cursor_to_world(
+    windows: &Windows,
+    camera: &Camera,
+    transform: &GlobalTransform,
+) -> Option<Vec2> {
+    let window = match camera.target {
+        RenderTarget::Window(id) => windows.get(id)?,
+        RenderTarget::Image(_) => return None,
+    };
+    let cursor = window.cursor_position()?;
+    let window_size = Vec2::new(window.width(), window.height());
+    let ndc = (cursor / window_size) * 2. - Vec2::ONE;
+    let ndc_to_world = transform.compute_matrix() * camera.projection_matrix.inverse();
+    Some(ndc_to_world.project_point3(ndc.extend(-1.)).truncate())
+}
+
+fn stick(
+    gamepad_axes: &Axis<GamepadAxis>,
+    gamepad: Gamepad,
+    x_axis: GamepadAxisType,
+    y_axis: GamepadAxisType,
+) -> Vec2 {
+    Vec2::new(
+        gamepad_axes
+            .get(GamepadAxis(gamepad, x_axis))
+            .unwrap_or_default(),
+        gamepad_axes
+            .get(GamepadAxis(gamepad, y_axis))
+            .unwrap_or_default(),
+    )
+}
+
+/// Ignores stick input inside the deadzone and rescales the rest to the full `0..=1` range,
+/// so the magnitude still grows smoothly from the edge of the deadzone
+fn apply_deadzone(stick: Vec2, deadzone: f32) -> Option<Vec2> {
+    let magnitude = stick.length();
+    if magnitude <= deadzone {
+        return None;
+    }
+    let scaled = ((magnitude - deadzone) / (1. - deadzone)).min(1.);
+    Some(stick / magnitude * scaled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadzone_ignores_small_deflections() {
+        assert_eq!(apply_deadzone(Vec2::ZERO, 0.2), None);
+        assert_eq!(apply_deadzone(Vec2::new(0.1, 0.1), 0.2), None);
+        assert_eq!(apply_deadzone(Vec2::new(0.2, 0.), 0.2), None);
+    }
+
+    #[test]
+    fn deadzone_rescales_to_the_full_range() {
+        let half = apply_deadzone(Vec2::new(0.6, 0.), 0.2).unwrap();
+        assert!((half - Vec2::new(0.5, 0.)).length() < 1e-5);
+
+        let full = apply_deadzone(Vec2::new(0., -1.), 0.2).unwrap();
+        assert!((full - Vec2::new(0., -1.)).length() < 1e-5);
+    }
+
+    #[test]
+    fn deadzone_keeps_the_direction_and_caps_the_magnitude() {
+        let stick = Vec2::new(1., 1.);
+        let output = apply_deadzone(stick, 0.3).unwrap();
+        assert!((output.length() - 1.).abs() < 1e-5);
+        assert!((output.normalize() - stick.normalize()).length() < 1e-5);
+    }
 }
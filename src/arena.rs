@@ -0,0 +1,685 @@
+use crate::camera::CameraBounds;
+use crate::level::Level;
+use crate::loading::{texture_or_default, TextureAssets};
+use crate::rng::{GameRng, RngStream, RngSystem};
+use crate::weapon::WeaponKind;
+use crate::{GameState, RunEntity};
+use anyhow::bail;
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::{
+    DrawMode, FillMode, GeometryBuilder, RectangleOrigin, StrokeMode,
+};
+use bevy_prototype_lyon::shapes;
+use bevy_rapier2d::prelude::*;
+use rand::Rng;
+use std::collections::VecDeque;
+
+pub struct ArenaPlugin;
+
+/// This plugin sets up the arena of every run and builds it
+/// The arena is either generated from the run's seed or read from a [Level], see [ArenaChoice]
+/// The [ArenaLayout] stays available as a resource, so other plugins can place things in the open
+impl Plugin for ArenaPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ArenaGenerator>()
+            .init_resource::<ArenaChoice>()
+            .init_resource::<ArenaLayout>()
+            .add_system_set(
+                SystemSet::on_enter(GameState::Playing)
+                    .label(ArenaSystem)
+                    .after(RngSystem)
+                    .with_system(choose_layout)
+                    .with_system(build_arena.after(choose_layout)),
+            );
+    }
+}
+
+/// Label of the systems setting up the arena of a new run
+/// Systems placing things in the arena must run after it
+#[derive(Clone, Debug, PartialEq, Eq, Hash, SystemLabel)]
+pub struct ArenaSystem;
+
+/// Which arena the next run is played in
+#[derive(Clone, Debug, PartialEq)]
+pub enum ArenaChoice {
+    /// A new arena from the [ArenaGenerator] for every run
+    Generated,
+    Level(Handle<Level>),
+}
+
+impl Default for ArenaChoice {
+    fn default() -> Self {
+        ArenaChoice::Generated
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Wall {
+    /// An axis aligned box
+    Rectangle { center: Vec2, half_extents: Vec2 },
+    /// A closed polygon in world coordinates, it doesn't need to be convex
+    Polygon(Vec<Vec2>),
+}
+
+impl Wall {
+    fn from_corners(min: Vec2, max: Vec2) -> Self {
+        Wall::Rectangle {
+            center: (min + max) / 2.,
+            half_extents: (max - min) / 2.,
+        }
+    }
+
+    /// Whether a circle overlaps the wall
+    pub fn overlaps(&self, point: Vec2, radius: f32) -> bool {
+        match self {
+            Wall::Rectangle {
+                center,
+                half_extents,
+            } => {
+                let closest = point.clamp(*center - *half_extents, *center + *half_extents);
+                closest.distance_squared(point) < radius * radius
+            }
+            Wall::Polygon(points) => {
+                let edges = points.iter().zip(points.iter().cycle().skip(1));
+                let mut inside = false;
+                for (&a, &b) in edges {
+                    if distance_to_segment(point, a, b) < radius {
+                        return true;
+                    }
+                    // even-odd rule, count the edges crossed by a ray to the right
+                    if (a.y > point.y) != (b.y > point.y)
+                        && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x)
+                    {
+                        inside = !inside;
+                    }
+                }
+                inside
+            }
+        }
+    }
+}
+
+fn distance_to_segment(point: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let segment = b - a;
+    let t = ((point - a).dot(segment) / segment.length_squared().max(f32::EPSILON)).clamp(0., 1.);
+    point.distance(a + segment * t)
+}
+
+/// Walls enclosing the area from `min` to `max`, lying just outside of it
+pub fn boundary_walls(min: Vec2, max: Vec2) -> [Wall; 4] {
+    let thickness = Vec2::splat(BOUNDARY_THICKNESS);
+    [
+        Wall::from_corners(min - thickness, Vec2::new(min.x, max.y + thickness.y)),
+        Wall::from_corners(Vec2::new(max.x, min.y - thickness.y), max + thickness),
+        Wall::from_corners(
+            Vec2::new(min.x, min.y - thickness.y),
+            Vec2::new(max.x, min.y),
+        ),
+        Wall::from_corners(
+            Vec2::new(min.x, max.y),
+            Vec2::new(max.x, max.y + thickness.y),
+        ),
+    ]
+}
+
+/// Thickness of the walls enclosing every arena
+const BOUNDARY_THICKNESS: f32 = 16.;
+
+/// Everything placed in an arena, both the colliders and the drawn geometry are built from it
+#[derive(Clone, Debug, Default)]
+pub struct ArenaLayout {
+    /// Lower left corner of the playable area
+    pub min: Vec2,
+    /// Upper right corner of the playable area
+    pub max: Vec2,
+    /// Edge length of the floor tiles
+    pub tile_size: f32,
+    pub walls: Vec<Wall>,
+    pub player_start: Vec2,
+    /// Where enemies enter the arena
+    pub spawn_points: Vec<Vec2>,
+    /// Weapons lying around at the start of a run
+    pub pickups: Vec<(WeaponKind, Vec2)>,
+}
+
+impl ArenaLayout {
+    /// Whether a circle overlaps any wall or sticks out of the playable area
+    pub fn is_blocked(&self, point: Vec2, radius: f32) -> bool {
+        point.cmplt(self.min + Vec2::splat(radius)).any()
+            || point.cmpgt(self.max - Vec2::splat(radius)).any()
+            || self.walls.iter().any(|wall| wall.overlaps(point, radius))
+    }
+}
+
+/// Weapons placed in every generated arena, as fractions of the distance from the center to the edges
+const PICKUPS: [(WeaponKind, [f32; 2]); 3] = [
+    (WeaponKind::Shotgun, [0.25, 0.25]),
+    (WeaponKind::Smg, [-0.25, 0.25]),
+    (WeaponKind::Rifle, [0., -0.4]),
+];
+
+/// Places clusters of cover on a grid of cells
+/// A cluster is only kept if every free cell can still be reached from the player start,
+/// so the arena never falls apart into separate areas
+pub struct ArenaGenerator {
+    /// Number of cells along each axis, the arena is centered on the origin
+    pub cells: IVec2,
+    /// Edge length of a cell in pixels
+    pub cell_size: f32,
+    /// Number of cover clusters the generator tries to place
+    pub cover_clusters: u32,
+    /// Radius around the player start that is kept free of cover
+    pub start_clearance: f32,
+    /// Radius around spawn points and pickups that is kept free of cover
+    pub spawn_clearance: f32,
+}
+
+impl Default for ArenaGenerator {
+    fn default() -> Self {
+        ArenaGenerator {
+            cells: IVec2::new(20, 20),
+            cell_size: 64.,
+            cover_clusters: 14,
+            start_clearance: 192.,
+            spawn_clearance: 96.,
+        }
+    }
+}
+
+/// A rectangle of cells, given by its lower left cell and its size in cells
+#[derive(Clone, Copy, Debug)]
+struct CellRect {
+    origin: IVec2,
+    size: IVec2,
+}
+
+impl CellRect {
+    fn new(origin: IVec2, size: IVec2) -> Self {
+        CellRect { origin, size }
+    }
+
+    fn cells(&self) -> impl Iterator<Item = IVec2> + '_ {
+        (self.origin.y..self.origin.y + self.size.y).flat_map(move |y| {
+            (self.origin.x..self.origin.x + self.size.x).map(move |x| IVec2::new(x, y))
+        })
+    }
+
+    fn grown(&self, by: i32) -> Self {
+        CellRect::new(
+            self.origin - IVec2::splat(by),
+            self.size + IVec2::splat(2 * by),
+        )
+    }
+}
+
+impl ArenaGenerator {
+    /// Catches configurations the generator can't build an arena from
+    pub fn validate(&self) -> anyhow::Result<()> {
+        // cover needs a cell between the outer ring and the center, spawn points a cell to the player start
+        if self.cells.cmplt(IVec2::splat(4)).any() {
+            bail!(
+                "the arena needs at least 4 cells along each axis, got {}",
+                self.cells
+            );
+        }
+        if self.cell_size <= 0. {
+            bail!("the cell size has to be positive");
+        }
+        if self.start_clearance < 0. || self.spawn_clearance < 0. {
+            bail!("the clearances can't be negative");
+        }
+        // everything has to lie on the cells inside of the outermost ring
+        let inner = self.max() - Vec2::splat(self.cell_size);
+        let start = Vec2::ZERO;
+        for point in self.spawn_points() {
+            if point.abs().cmpgt(inner).any() || point.distance(start) < self.start_clearance {
+                bail!(
+                    "spawn point {} is outside of the arena or within the player start's clearance",
+                    point
+                );
+            }
+        }
+        for (kind, point) in self.pickups() {
+            if point.abs().cmpgt(inner).any() || point.distance(start) < self.spawn_clearance {
+                bail!(
+                    "the {} is outside of the arena or too close to the player start",
+                    kind.name()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Upper right corner of the arena, it is centered on the origin
+    fn max(&self) -> Vec2 {
+        self.cells.as_vec2() * self.cell_size / 2.
+    }
+
+    /// Spawn points sit in the corners and in the middle of the edges
+    fn spawn_points(&self) -> Vec<Vec2> {
+        let inset = self.max() - Vec2::splat(self.cell_size * 1.5);
+        let mut spawn_points = Vec::new();
+        for y in -1..=1 {
+            for x in -1..=1 {
+                if x != 0 || y != 0 {
+                    spawn_points.push(Vec2::new(x as f32, y as f32) * inset);
+                }
+            }
+        }
+        spawn_points
+    }
+
+    fn pickups(&self) -> Vec<(WeaponKind, Vec2)> {
+        PICKUPS
+            .iter()
+            .map(|&(kind, position)| (kind, Vec2::from(position) * self.max()))
+            .collect()
+    }
+
+    /// Expects a valid configuration, see [ArenaGenerator::validate]
+    pub fn generate(&self, rng: &mut impl Rng) -> ArenaLayout {
+        let max = self.max();
+        let min = -max;
+        let player_start = Vec2::ZERO;
+        let spawn_points = self.spawn_points();
+        let pickups = self.pickups();
+
+        let mut clearings = vec![(player_start, self.start_clearance)];
+        clearings.extend(
+            spawn_points
+                .iter()
+                .map(|&point| (point, self.spawn_clearance)),
+        );
+        clearings.extend(
+            pickups
+                .iter()
+                .map(|&(_, point)| (point, self.spawn_clearance)),
+        );
+
+        let mut blocked = vec![false; (self.cells.x * self.cells.y) as usize];
+        let start_cell = self.cell_at(min, player_start);
+        let mut cover = Vec::new();
+        let mut placed = 0;
+        for _ in 0..self.cover_clusters * 8 {
+            if placed == self.cover_clusters {
+                break;
+            }
+            let origin = IVec2::new(
+                rng.gen_range(1..self.cells.x - 1),
+                rng.gen_range(1..self.cells.y - 1),
+            );
+            let cluster: Vec<CellRect> = random_cluster(rng)
+                .into_iter()
+                .map(|rect| CellRect::new(rect.origin + origin, rect.size))
+                .collect();
+            if !cluster
+                .iter()
+                .all(|rect| self.fits(rect, min, &blocked, &clearings))
+            {
+                continue;
+            }
+
+            for cell in cluster.iter().flat_map(CellRect::cells) {
+                let index = self.index(cell);
+                blocked[index] = true;
+            }
+            if !self.is_connected(&blocked, start_cell) {
+                for cell in cluster.iter().flat_map(CellRect::cells) {
+                    let index = self.index(cell);
+                    blocked[index] = false;
+                }
+                continue;
+            }
+            cover.extend(cluster);
+            placed += 1;
+        }
+
+        let mut walls: Vec<Wall> = cover
+            .iter()
+            .map(|rect| {
+                Wall::from_corners(
+                    self.cell_min(min, rect.origin),
+                    self.cell_min(min, rect.origin + rect.size),
+                )
+            })
+            .collect();
+        walls.extend(boundary_walls(min, max));
+
+        ArenaLayout {
+            min,
+            max,
+            tile_size: self.cell_size,
+            walls,
+            player_start,
+            spawn_points,
+            pickups,
+        }
+    }
+
+    fn index(&self, cell: IVec2) -> usize {
+        (cell.y * self.cells.x + cell.x) as usize
+    }
+
+    fn in_bounds(&self, cell: IVec2) -> bool {
+        cell.cmpge(IVec2::ZERO).all() && cell.cmplt(self.cells).all()
+    }
+
+    fn cell_min(&self, min: Vec2, cell: IVec2) -> Vec2 {
+        min + cell.as_vec2() * self.cell_size
+    }
+
+    fn cell_at(&self, min: Vec2, point: Vec2) -> IVec2 {
+        ((point - min) / self.cell_size)
+            .floor()
+            .as_ivec2()
+            .clamp(IVec2::ZERO, self.cells - IVec2::ONE)
+    }
+
+    /// Cover keeps the outermost ring of cells free, a gap of one cell to other cover
+    /// and its distance to the clearings
+    fn fits(
+        &self,
+        rect: &CellRect,
+        min: Vec2,
+        blocked: &[bool],
+        clearings: &[(Vec2, f32)],
+    ) -> bool {
+        let inner = CellRect::new(IVec2::ONE, self.cells - IVec2::splat(2));
+        let inside = rect.origin.cmpge(inner.origin).all()
+            && (rect.origin + rect.size)
+                .cmple(inner.origin + inner.size)
+                .all();
+        if !inside {
+            return false;
+        }
+        if rect
+            .grown(1)
+            .cells()
+            .any(|cell| self.in_bounds(cell) && blocked[self.index(cell)])
+        {
+            return false;
+        }
+        let wall = Wall::from_corners(
+            self.cell_min(min, rect.origin),
+            self.cell_min(min, rect.origin + rect.size),
+        );
+        clearings
+            .iter()
+            .all(|&(point, radius)| !wall.overlaps(point, radius))
+    }
+
+    /// Whether every free cell can be reached from `start`
+    fn is_connected(&self, blocked: &[bool], start: IVec2) -> bool {
+        let mut visited = vec![false; blocked.len()];
+        let mut queue = VecDeque::from([start]);
+        visited[self.index(start)] = true;
+        let mut reached = 1;
+        while let Some(cell) = queue.pop_front() {
+            for offset in [IVec2::X, -IVec2::X, IVec2::Y, -IVec2::Y] {
+                let next = cell + offset;
+                if !self.in_bounds(next) {
+                    continue;
+                }
+                let index = self.index(next);
+                if blocked[index] || visited[index] {
+                    continue;
+                }
+                visited[index] = true;
+                reached += 1;
+                queue.push_back(next);
+            }
+        }
+        reached == blocked.iter().filter(|blocked| !**blocked).count()
+    }
+}
+
+/// A block, a bar or an L shaped piece of cover, relative to its origin cell
+fn random_cluster(rng: &mut impl Rng) -> Vec<CellRect> {
+    match rng.gen_range(0..3) {
+        0 => vec![CellRect::new(
+            IVec2::ZERO,
+            IVec2::new(rng.gen_range(1..=2), rng.gen_range(1..=2)),
+        )],
+        1 => {
+            let length = rng.gen_range(2..=4);
+            let size = if rng.gen() {
+                IVec2::new(length, 1)
+            } else {
+                IVec2::new(1, length)
+            };
+            vec![CellRect::new(IVec2::ZERO, size)]
+        }
+        _ => {
+            let width = rng.gen_range(2..=3);
+            let height = rng.gen_range(1..=2);
+            let x = if rng.gen() { 0 } else { width - 1 };
+            let y = if rng.gen() { 1 } else { -height };
+            vec![
+                CellRect::new(IVec2::ZERO, IVec2::new(width, 1)),
+                CellRect::new(IVec2::new(x, y), IVec2::new(1, height)),
+            ]
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct ArenaWall;
+
+#[derive(Component)]
+struct FloorTile;
+
+fn choose_layout(
+    choice: Res<ArenaChoice>,
+    levels: Res<Assets<Level>>,
+    generator: Res<ArenaGenerator>,
+    mut layout: ResMut<ArenaLayout>,
+    mut rng: ResMut<GameRng>,
+) {
+    if let ArenaChoice::Level(handle) = &*choice {
+        match levels.get(handle) {
+            Some(level) => {
+                *layout = level.layout();
+                return;
+            }
+            None => warn!("The chosen level isn't loaded, generating an arena instead"),
+        }
+    }
+    match generator.validate() {
+        Ok(()) => *layout = generator.generate(rng.stream(RngStream::World)),
+        Err(error) => {
+            warn!(
+                "Invalid arena generator, using the default one: {:?}",
+                error
+            );
+            *layout = ArenaGenerator::default().generate(rng.stream(RngStream::World));
+        }
+    }
+}
+
+fn build_arena(
+    mut commands: Commands,
+    layout: Res<ArenaLayout>,
+    textures: Option<Res<TextureAssets>>,
+) {
+    commands.insert_resource(CameraBounds {
+        min: layout.min,
+        max: layout.max,
+    });
+
+    let ground = texture_or_default(&textures, |textures| &textures.texture_ground);
+    let tiles = ((layout.max - layout.min) / layout.tile_size)
+        .ceil()
+        .as_ivec2();
+    for y in 0..tiles.y {
+        for x in 0..tiles.x {
+            let center = layout.min + (Vec2::new(x as f32, y as f32) + 0.5) * layout.tile_size;
+            commands
+                .spawn_bundle(SpriteBundle {
+                    texture: ground.clone(),
+                    transform: Transform::from_translation(center.extend(0.)),
+                    sprite: Sprite {
+                        custom_size: Some(Vec2::splat(layout.tile_size)),
+                        color: Color::rgba(0.5, 0.5, 0.5, 0.5),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(FloorTile)
+                .insert(RunEntity);
+        }
+    }
+
+    for wall in layout.walls.iter() {
+        spawn_wall(&mut commands, wall);
+    }
+}
+
+pub fn spawn_wall(commands: &mut Commands, wall: &Wall) -> Entity {
+    let draw_mode = DrawMode::Outlined {
+        fill_mode: FillMode::color(Color::GRAY),
+        outline_mode: StrokeMode::new(Color::DARK_GRAY, 2.),
+    };
+    let (shape, collider) = match wall {
+        Wall::Rectangle {
+            center,
+            half_extents,
+        } => (
+            GeometryBuilder::build_as(
+                &shapes::Rectangle {
+                    extents: *half_extents * 2.,
+                    origin: RectangleOrigin::Center,
+                },
+                draw_mode,
+                Transform::from_translation(center.extend(0.2)),
+            ),
+            Collider::cuboid(half_extents.x, half_extents.y),
+        ),
+        Wall::Polygon(points) => {
+            let count = points.len() as u32;
+            let edges: Vec<[u32; 2]> = (0..count).map(|i| [i, (i + 1) % count]).collect();
+            (
+                GeometryBuilder::build_as(
+                    &shapes::Polygon {
+                        points: points.clone(),
+                        closed: true,
+                    },
+                    draw_mode,
+                    Transform::from_xyz(0., 0., 0.2),
+                ),
+                // concave walls are split into convex pieces
+                Collider::convex_decomposition(points, &edges),
+            )
+        }
+    };
+    commands
+        .spawn_bundle(shape)
+        .insert(collider)
+        .insert(ArenaWall)
+        .insert(RunEntity)
+        .id()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// The cover walls, the boundary walls come last
+    fn cover(layout: &ArenaLayout) -> &[Wall] {
+        &layout.walls[..layout.walls.len() - 4]
+    }
+
+    #[test]
+    fn generated_arenas_are_connected_and_keep_clearings() {
+        let generator = ArenaGenerator::default();
+        for seed in 0..300 {
+            let layout = generator.generate(&mut StdRng::seed_from_u64(seed));
+            let cover = cover(&layout);
+            assert!(!cover.is_empty(), "seed {} placed no cover", seed);
+
+            let mut blocked = vec![false; (generator.cells.x * generator.cells.y) as usize];
+            for y in 0..generator.cells.y {
+                for x in 0..generator.cells.x {
+                    let cell = IVec2::new(x, y);
+                    let center = generator.cell_min(layout.min, cell) + generator.cell_size / 2.;
+                    blocked[generator.index(cell)] =
+                        cover.iter().any(|wall| wall.overlaps(center, 1.));
+                }
+            }
+            let start = generator.cell_at(layout.min, layout.player_start);
+            assert!(
+                generator.is_connected(&blocked, start),
+                "seed {} is split up",
+                seed
+            );
+
+            let clear =
+                |point: Vec2, radius: f32| !cover.iter().any(|wall| wall.overlaps(point, radius));
+            assert!(
+                clear(layout.player_start, generator.start_clearance),
+                "seed {} covers the player start",
+                seed
+            );
+            for &point in layout
+                .spawn_points
+                .iter()
+                .chain(layout.pickups.iter().map(|(_, point)| point))
+            {
+                assert!(
+                    clear(point, generator.spawn_clearance),
+                    "seed {} covers {}",
+                    seed,
+                    point
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_arenas_without_room_for_cover() {
+        assert!(ArenaGenerator::default().validate().is_ok());
+        for cells in [IVec2::new(2, 20), IVec2::new(20, 3), IVec2::ZERO] {
+            let generator = ArenaGenerator {
+                cells,
+                ..Default::default()
+            };
+            assert!(generator.validate().is_err());
+        }
+        let generator = ArenaGenerator {
+            cell_size: 0.,
+            ..Default::default()
+        };
+        assert!(generator.validate().is_err());
+        // the spawn points in the middle of the edges would be within the player start's clearance
+        let generator = ArenaGenerator {
+            cells: IVec2::new(8, 8),
+            ..Default::default()
+        };
+        assert!(generator.validate().is_err());
+    }
+
+    #[test]
+    fn small_arenas_keep_everything_inside() {
+        let generator = ArenaGenerator {
+            cells: IVec2::new(12, 12),
+            ..Default::default()
+        };
+        assert!(generator.validate().is_ok());
+        for seed in 0..50 {
+            let layout = generator.generate(&mut StdRng::seed_from_u64(seed));
+            for &point in layout
+                .spawn_points
+                .iter()
+                .chain(layout.pickups.iter().map(|(_, point)| point))
+            {
+                assert!(
+                    !layout.is_blocked(point, 16.),
+                    "seed {} blocks {}",
+                    seed,
+                    point
+                );
+            }
+        }
+    }
+}
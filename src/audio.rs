@@ -1,5 +1,6 @@
 use crate::actions::Actions;
 use crate::loading::AudioAssets;
+use crate::settings::Settings;
 use crate::GameState;
 use bevy::prelude::*;
 use bevy_kira_audio::{Audio, AudioApp, AudioChannel, AudioPlugin};
@@ -8,11 +9,15 @@ pub struct InternalAudioPlugin;
 
 struct Walking;
 
+/// Mixes the loops down, so the volume settings scale between silence and a comfortable level
+const SFX_GAIN: f32 = 0.3;
+
 // This plugin is responsible to control the game audio
 impl Plugin for InternalAudioPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugin(AudioPlugin)
             .add_audio_channel::<Walking>()
+            .add_system(apply_volumes)
             .add_system_set(
                 SystemSet::on_enter(GameState::Playing)
                     .with_system(start_shoot_audio)
@@ -20,22 +25,34 @@ impl Plugin for InternalAudioPlugin {
             )
             .add_system_set(
                 SystemSet::on_update(GameState::Playing).with_system(control_shooting_sound),
-            );
+            )
+            .add_system_set(SystemSet::on_pause(GameState::Playing).with_system(pause_audio))
+            .add_system_set(SystemSet::on_exit(GameState::Playing).with_system(stop_audio));
     }
 }
 
 fn start_shoot_audio(audio_assets: Res<AudioAssets>, audio: Res<Audio>) {
-    audio.set_volume(0.3);
     audio.play_looped(audio_assets.shooting.clone());
     audio.pause();
 }
 
 fn start_walk_audio(background: Res<AudioChannel<Walking>>, audio_assets: Res<AudioAssets>) {
-    background.set_volume(0.3);
     background.play_looped(audio_assets.walking.clone());
     background.pause();
 }
 
+fn apply_volumes(
+    settings: Res<Settings>,
+    audio: Res<Audio>,
+    walk_audio: Res<AudioChannel<Walking>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    audio.set_volume(SFX_GAIN * settings.sfx_gain());
+    walk_audio.set_volume(SFX_GAIN * settings.sfx_gain());
+}
+
 fn control_shooting_sound(
     actions: Res<Actions>,
     audio: Res<Audio>,
@@ -52,3 +69,13 @@ fn control_shooting_sound(
         walk_audio.pause()
     }
 }
+
+fn pause_audio(audio: Res<Audio>, walk_audio: Res<AudioChannel<Walking>>) {
+    audio.pause();
+    walk_audio.pause();
+}
+
+fn stop_audio(audio: Res<Audio>, walk_audio: Res<AudioChannel<Walking>>) {
+    audio.stop();
+    walk_audio.stop();
+}
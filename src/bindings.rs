@@ -0,0 +1,227 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A logical action the player can trigger
+/// Physical inputs are mapped to these through the [KeyBindings] resource
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GameControl {
+    Up,
+    Down,
+    Left,
+    Right,
+    MouseGrab,
+    Trigger,
+    Reload,
+    Dash,
+    Weapon1,
+    Weapon2,
+    Weapon3,
+    Weapon4,
+    Weapon5,
+    NextWeapon,
+    PreviousWeapon,
+    Pause,
+}
+
+impl GameControl {
+    /// Every control, in the order they are listed in the settings
+    pub const ALL: [GameControl; 16] = [
+        GameControl::Up,
+        GameControl::Down,
+        GameControl::Left,
+        GameControl::Right,
+        GameControl::MouseGrab,
+        GameControl::Trigger,
+        GameControl::Reload,
+        GameControl::Dash,
+        GameControl::Weapon1,
+        GameControl::Weapon2,
+        GameControl::Weapon3,
+        GameControl::Weapon4,
+        GameControl::Weapon5,
+        GameControl::NextWeapon,
+        GameControl::PreviousWeapon,
+        GameControl::Pause,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            GameControl::Up => "Move up",
+            GameControl::Down => "Move down",
+            GameControl::Left => "Move left",
+            GameControl::Right => "Move right",
+            GameControl::MouseGrab => "Grab mouse",
+            GameControl::Trigger => "Shoot",
+            GameControl::Reload => "Reload",
+            GameControl::Dash => "Dash",
+            GameControl::Weapon1 => "Weapon 1",
+            GameControl::Weapon2 => "Weapon 2",
+            GameControl::Weapon3 => "Weapon 3",
+            GameControl::Weapon4 => "Weapon 4",
+            GameControl::Weapon5 => "Weapon 5",
+            GameControl::NextWeapon => "Next weapon",
+            GameControl::PreviousWeapon => "Previous weapon",
+            GameControl::Pause => "Pause",
+        }
+    }
+}
+
+/// A single physical input that can be bound to a [GameControl]
+/// Gamepad buttons match on any connected gamepad
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum InputBinding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButtonType),
+}
+
+impl fmt::Display for InputBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputBinding::Key(key) => write!(f, "{:?}", key),
+            InputBinding::Mouse(button) => write!(f, "Mouse {:?}", button),
+            InputBinding::Gamepad(button) => write!(f, "Pad {:?}", button),
+        }
+    }
+}
+
+/// Maps every [GameControl] to any number of physical inputs
+///
+/// The defaults are loaded from `assets/controls/default.bindings.ron` during `GameState::Loading`
+/// and can be changed at runtime through [KeyBindings::bind], [KeyBindings::unbind] and [KeyBindings::rebind]
+#[derive(Clone, Debug, Serialize, Deserialize, TypeUuid)]
+#[uuid = "5a3c9e0f-7d1b-4e62-9a8c-2f4b6d1e8c73"]
+pub struct KeyBindings {
+    bindings: HashMap<GameControl, Vec<InputBinding>>,
+}
+
+/// The bindings shipped with the game, the same file is loaded as an asset
+const DEFAULT_BINDINGS: &str = include_str!("../assets/controls/default.bindings.ron");
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        ron::from_str(DEFAULT_BINDINGS).expect("the default bindings are invalid")
+    }
+}
+
+impl KeyBindings {
+    /// All inputs currently bound to the given control
+    pub fn get(&self, control: GameControl) -> &[InputBinding] {
+        self.bindings
+            .get(&control)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Adds an input to a control, keeping the existing ones
+    pub fn bind(&mut self, control: GameControl, binding: InputBinding) {
+        let bindings = self.bindings.entry(control).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    /// Removes an input from a control
+    pub fn unbind(&mut self, control: GameControl, binding: InputBinding) {
+        if let Some(bindings) = self.bindings.get_mut(&control) {
+            bindings.retain(|bound| *bound != binding);
+        }
+    }
+
+    /// Replaces all inputs of a control
+    pub fn rebind(&mut self, control: GameControl, bindings: Vec<InputBinding>) {
+        self.bindings.insert(control, bindings);
+    }
+
+    pub fn pressed(&self, control: GameControl, input: &ControlInput) -> bool {
+        self.get(control)
+            .iter()
+            .any(|binding| input.pressed(binding))
+    }
+
+    pub fn just_pressed(&self, control: GameControl, input: &ControlInput) -> bool {
+        self.get(control)
+            .iter()
+            .any(|binding| input.just_pressed(binding))
+    }
+
+    pub fn just_released(&self, control: GameControl, input: &ControlInput) -> bool {
+        self.get(control)
+            .iter()
+            .any(|binding| input.just_released(binding))
+    }
+}
+
+/// Bundles all input resources a [InputBinding] can be checked against
+#[derive(SystemParam)]
+pub struct ControlInput<'w, 's> {
+    keyboard: Res<'w, Input<KeyCode>>,
+    mouse: Res<'w, Input<MouseButton>>,
+    gamepad_buttons: Res<'w, Input<GamepadButton>>,
+    gamepads: Res<'w, Gamepads>,
+    #[system_param(ignore)]
+    marker: std::marker::PhantomData<&'s ()>,
+}
+
+impl<'w, 's> ControlInput<'w, 's> {
+    pub fn pressed(&self, binding: &InputBinding) -> bool {
+        match *binding {
+            InputBinding::Key(key) => self.keyboard.pressed(key),
+            InputBinding::Mouse(button) => self.mouse.pressed(button),
+            InputBinding::Gamepad(button) => self.gamepads.iter().any(|gamepad| {
+                self.gamepad_buttons
+                    .pressed(GamepadButton(*gamepad, button))
+            }),
+        }
+    }
+
+    pub fn just_pressed(&self, binding: &InputBinding) -> bool {
+        match *binding {
+            InputBinding::Key(key) => self.keyboard.just_pressed(key),
+            InputBinding::Mouse(button) => self.mouse.just_pressed(button),
+            InputBinding::Gamepad(button) => self.gamepads.iter().any(|gamepad| {
+                self.gamepad_buttons
+                    .just_pressed(GamepadButton(*gamepad, button))
+            }),
+        }
+    }
+
+    pub fn just_released(&self, binding: &InputBinding) -> bool {
+        match *binding {
+            InputBinding::Key(key) => self.keyboard.just_released(key),
+            InputBinding::Mouse(button) => self.mouse.just_released(button),
+            InputBinding::Gamepad(button) => self.gamepads.iter().any(|gamepad| {
+                self.gamepad_buttons
+                    .just_released(GamepadButton(*gamepad, button))
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_bindings_cover_every_control() {
+        let bindings = KeyBindings::default();
+        for control in GameControl::ALL {
+            assert!(
+                bindings.bindings.contains_key(&control),
+                "{:?} is missing from the default bindings",
+                control
+            );
+        }
+        assert_eq!(
+            bindings.get(GameControl::Dash),
+            &[
+                InputBinding::Key(KeyCode::Space),
+                InputBinding::Gamepad(GamepadButtonType::South)
+            ]
+        );
+    }
+}
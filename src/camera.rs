@@ -0,0 +1,200 @@
+use crate::actions::Actions;
+use crate::player::Player;
+use crate::rng::{GameRng, RngStream};
+use crate::timestep::InterpolationSystem;
+use crate::{GameState, RunEntity};
+use bevy::prelude::*;
+use bevy::transform::TransformSystem;
+use rand::Rng;
+
+pub struct CameraControllerPlugin;
+
+/// This plugin spawns the game camera and lets it follow the player
+/// Other plugins can shake the camera by sending a [CameraShakeEvent]
+impl Plugin for CameraControllerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CameraSettings>()
+            .init_resource::<ViewportSize>()
+            .init_resource::<CameraShake>()
+            .add_event::<CameraShakeEvent>()
+            .add_system_to_stage(CoreStage::PreUpdate, track_viewport_size)
+            .add_system_set(SystemSet::on_enter(GameState::Playing).with_system(spawn_camera))
+            .add_system_set_to_stage(
+                CoreStage::PostUpdate,
+                SystemSet::on_update(GameState::Playing)
+                    .before(TransformSystem::TransformPropagate)
+                    .after(InterpolationSystem)
+                    .with_system(follow_player)
+                    .with_system(add_trauma.before(shake_camera))
+                    .with_system(shake_camera.after(follow_player)),
+            );
+    }
+}
+
+/// The camera the game world is rendered and aimed through
+#[derive(Component)]
+pub struct MainCamera;
+
+/// The point the camera looks at before any shake is applied
+#[derive(Component, Default)]
+pub struct CameraRig {
+    pub focus: Vec2,
+}
+
+/// Size of the area the game world is shown in, in logical pixels
+/// Follows the primary window, without a window (e.g. in the headless app) the configured size is kept
+#[derive(Clone, Copy, Debug)]
+pub struct ViewportSize(pub Vec2);
+
+impl Default for ViewportSize {
+    fn default() -> Self {
+        ViewportSize(Vec2::new(1200., 800.))
+    }
+}
+
+impl ViewportSize {
+    /// Half of the world area visible through a camera with the given zoom
+    pub fn half_view(&self, projection: &OrthographicProjection) -> Vec2 {
+        self.0 * projection.scale / 2.
+    }
+
+    /// Like [ViewportSize::half_view], without a camera the view isn't zoomed
+    pub fn half_view_at(&self, projection: Option<&OrthographicProjection>) -> Vec2 {
+        projection.map_or(self.0 / 2., |projection| self.half_view(projection))
+    }
+}
+
+/// The area the camera view is kept inside of
+pub struct CameraBounds {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+pub struct CameraSettings {
+    /// How quickly the camera catches up with its target, higher is snappier
+    pub smoothing: f32,
+    /// How far the camera leads the player in the aim direction
+    pub look_ahead: f32,
+    /// Half extents of the box around the camera focus the player can move in without the camera following
+    pub dead_zone: Vec2,
+    /// Offset in pixels at full trauma
+    pub max_shake_offset: f32,
+    /// Rotation in radians at full trauma
+    pub max_shake_angle: f32,
+    /// Trauma removed per second
+    pub trauma_decay: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        CameraSettings {
+            smoothing: 6.,
+            look_ahead: 80.,
+            dead_zone: Vec2::new(24., 16.),
+            max_shake_offset: 12.,
+            max_shake_angle: 0.05,
+            trauma_decay: 1.5,
+        }
+    }
+}
+
+/// Current trauma of the camera between 0 and 1
+/// The actual shake grows with the square of the trauma, so small hits barely move the view
+#[derive(Default)]
+pub struct CameraShake {
+    pub trauma: f32,
+}
+
+/// Adds trauma to the camera shake
+pub struct CameraShakeEvent {
+    pub trauma: f32,
+}
+
+fn spawn_camera(mut commands: Commands, mut shake: ResMut<CameraShake>) {
+    shake.trauma = 0.;
+    commands
+        .spawn_bundle(OrthographicCameraBundle::new_2d())
+        .insert(CameraRig::default())
+        .insert(MainCamera)
+        .insert(RunEntity);
+}
+
+fn track_viewport_size(windows: Res<Windows>, mut viewport: ResMut<ViewportSize>) {
+    if let Some(window) = windows.get_primary() {
+        let size = Vec2::new(window.width(), window.height());
+        if viewport.0 != size {
+            viewport.0 = size;
+        }
+    }
+}
+
+fn follow_player(
+    time: Res<Time>,
+    actions: Res<Actions>,
+    settings: Res<CameraSettings>,
+    viewport: Res<ViewportSize>,
+    bounds: Option<Res<CameraBounds>>,
+    player_query: Query<&Transform, (With<Player>, Without<MainCamera>)>,
+    mut camera_query: Query<(&mut CameraRig, &OrthographicProjection), With<MainCamera>>,
+) {
+    let player_position = match player_query.get_single() {
+        Ok(transform) => transform.translation.truncate(),
+        Err(_) => return,
+    };
+    let look_ahead = actions.aim_direction.unwrap_or(Vec2::ZERO) * settings.look_ahead;
+    let desired = player_position + look_ahead;
+
+    for (mut rig, projection) in camera_query.iter_mut() {
+        let offset = desired - rig.focus;
+        let target = rig.focus + offset - offset.clamp(-settings.dead_zone, settings.dead_zone);
+        let t = 1. - (-settings.smoothing * time.delta_seconds()).exp();
+        let mut focus = rig.focus.lerp(target, t);
+
+        if let Some(bounds) = &bounds {
+            let half_view = viewport.half_view(projection);
+            focus.x = clamp_to_bounds(focus.x, bounds.min.x, bounds.max.x, half_view.x);
+            focus.y = clamp_to_bounds(focus.y, bounds.min.y, bounds.max.y, half_view.y);
+        }
+
+        rig.focus = focus;
+    }
+}
+
+/// Keeps a view with the given half extent inside `min..max`, centering it if the view is larger
+fn clamp_to_bounds(value: f32, min: f32, max: f32, half_view: f32) -> f32 {
+    if max - min <= half_view * 2. {
+        (min + max) / 2.
+    } else {
+        value.clamp(min + half_view, max - half_view)
+    }
+}
+
+fn add_trauma(mut shake: ResMut<CameraShake>, mut events: EventReader<CameraShakeEvent>) {
+    for event in events.iter() {
+        shake.trauma = (shake.trauma + event.trauma).min(1.);
+    }
+}
+
+fn shake_camera(
+    time: Res<Time>,
+    settings: Res<CameraSettings>,
+    mut shake: ResMut<CameraShake>,
+    mut game_rng: ResMut<GameRng>,
+    mut camera_query: Query<(&CameraRig, &mut Transform), With<MainCamera>>,
+) {
+    let amount = shake.trauma * shake.trauma;
+    let rng = game_rng.stream(RngStream::Cosmetic);
+
+    for (rig, mut transform) in camera_query.iter_mut() {
+        let offset = Vec2::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0))
+            * settings.max_shake_offset
+            * amount;
+        let angle = rng.gen_range(-1.0..1.0) * settings.max_shake_angle * amount;
+
+        transform.translation.x = rig.focus.x + offset.x;
+        transform.translation.y = rig.focus.y + offset.y;
+        transform.rotation = Quat::from_rotation_z(angle);
+    }
+
+    shake.trauma = (shake.trauma - settings.trauma_decay * time.delta_seconds()).max(0.);
+}
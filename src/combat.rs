@@ -0,0 +1,387 @@
+use crate::camera::CameraShakeEvent;
+use crate::enemy::Zombie;
+use crate::player::{Player, PLAYER_RADIUS};
+use crate::timestep::{FixedTime, FixedUpdateAppExt};
+use crate::weapon::{spawn_explosion_flash, Bullet, ProjectileBehaviour};
+use crate::GameState;
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use bevy_rapier2d::prelude::*;
+use bevy_rapier2d::rapier::geometry::InteractionGroups;
+
+pub struct CombatPlugin;
+
+/// This plugin turns bullet collisions into [DamageEvent]s and applies them to [Health]
+/// Entities running out of health send a [KillEvent], so other plugins can react to hits and kills
+impl Plugin for CombatPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DamageEvent>()
+            .add_event::<DamageTakenEvent>()
+            .add_event::<KillEvent>()
+            .add_event::<ExplosionEvent>()
+            .add_fixed_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .label(CombatSystem)
+                    .with_system(bullet_hits)
+                    .with_system(explode.after(bullet_hits))
+                    .with_system(apply_damage.after(explode))
+                    .with_system(despawn_killed_zombies.after(apply_damage))
+                    .with_system(zombie_contact_damage.before(apply_damage))
+                    .with_system(tick_invulnerability),
+            )
+            // only shakes the camera, so it can run once per frame
+            .add_system_set(SystemSet::on_update(GameState::Playing).with_system(shake_on_hit));
+    }
+}
+
+/// Label of the combat systems in the fixed timestep, systems reacting to [DamageEvent]s
+/// and [KillEvent]s of the current tick should run after it
+#[derive(Clone, Debug, PartialEq, Eq, Hash, SystemLabel)]
+pub struct CombatSystem;
+
+#[derive(Component, Debug)]
+pub struct Health {
+    pub current: u32,
+    pub max: u32,
+}
+
+impl Health {
+    pub fn new(max: u32) -> Self {
+        Health { current: max, max }
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current == 0
+    }
+}
+
+/// Entities with this component can't be hurt until the timer finishes
+#[derive(Component)]
+pub struct Invulnerable(pub Timer);
+
+impl Invulnerable {
+    pub fn for_seconds(seconds: f32) -> Self {
+        Invulnerable(Timer::from_seconds(seconds, false))
+    }
+}
+
+/// Velocity pushing an entity away after a hit, fading out over time
+#[derive(Component, Default)]
+pub struct Knockback {
+    pub velocity: Vec2,
+}
+
+impl Knockback {
+    /// Fraction of the knockback velocity lost per second
+    const DAMPING: f32 = 8.;
+
+    pub fn decay(&mut self, delta_seconds: f32) {
+        self.velocity *= (-Self::DAMPING * delta_seconds).exp();
+    }
+}
+
+/// Seconds the player can't be hurt again after being hit by a zombie
+const PLAYER_INVULNERABILITY: f32 = 1.;
+const PLAYER_KNOCKBACK_SPEED: f32 = 400.;
+
+/// Sent whenever an entity with [Health] is hit
+pub struct DamageEvent {
+    pub target: Entity,
+    /// The entity that dealt the damage, e.g. a bullet
+    pub source: Entity,
+    pub amount: u32,
+    pub position: Vec2,
+}
+
+/// Sent for every [DamageEvent] that actually lowered an entity's [Health]
+pub struct DamageTakenEvent {
+    pub target: Entity,
+    pub source: Entity,
+    /// Health lost, at most the health that was left
+    pub amount: u32,
+    pub position: Vec2,
+}
+
+/// Sent when an explosive projectile goes off
+pub struct ExplosionEvent {
+    pub source: Entity,
+    pub position: Vec2,
+    pub radius: f32,
+    /// Damage at the center of the blast, it falls off to half at the edge
+    pub damage: u32,
+    /// Speed in pixels per second bodies at the center are pushed away with
+    pub knockback: f32,
+}
+
+/// Sent once when an entity's [Health] drops to zero
+pub struct KillEvent {
+    pub target: Entity,
+    pub source: Entity,
+    pub position: Vec2,
+}
+
+fn bullet_hits(
+    mut commands: Commands,
+    mut collision_events: EventReader<CollisionEvent>,
+    mut damage_events: EventWriter<DamageEvent>,
+    mut explosion_events: EventWriter<ExplosionEvent>,
+    mut bullet_query: Query<(&mut Bullet, &Transform)>,
+    zombie_query: Query<&Transform, With<Zombie>>,
+) {
+    let mut consumed = HashSet::default();
+    for event in collision_events.iter() {
+        let (first, second) = match event {
+            CollisionEvent::Started(first, second, _) => (*first, *second),
+            CollisionEvent::Stopped(..) => continue,
+        };
+        let (bullet_entity, other) = if bullet_query.contains(first) {
+            (first, second)
+        } else {
+            (second, first)
+        };
+        if consumed.contains(&bullet_entity) {
+            continue;
+        }
+        let (mut bullet, bullet_transform) = match bullet_query.get_mut(bullet_entity) {
+            Ok(bullet) => bullet,
+            Err(_) => continue,
+        };
+        // anything that isn't a zombie is a wall
+        let zombie_position = zombie_query
+            .get(other)
+            .ok()
+            .map(|transform| transform.translation.truncate());
+
+        if let (Some(position), false) = (
+            zombie_position,
+            matches!(bullet.behaviour, ProjectileBehaviour::Explosive { .. }),
+        ) {
+            damage_events.send(DamageEvent {
+                target: other,
+                source: bullet_entity,
+                amount: bullet.damage,
+                position,
+            });
+        }
+
+        let spent = match &mut bullet.behaviour {
+            ProjectileBehaviour::Single => true,
+            ProjectileBehaviour::Piercing { hits } => {
+                if zombie_position.is_some() {
+                    *hits = hits.saturating_sub(1);
+                    *hits == 0
+                } else {
+                    true
+                }
+            }
+            ProjectileBehaviour::Bouncing { bounces } => {
+                if zombie_position.is_some() || *bounces == 0 {
+                    true
+                } else {
+                    *bounces -= 1;
+                    false
+                }
+            }
+            ProjectileBehaviour::Explosive {
+                radius,
+                damage,
+                knockback,
+            } => {
+                explosion_events.send(ExplosionEvent {
+                    source: bullet_entity,
+                    position: bullet_transform.translation.truncate(),
+                    radius: *radius,
+                    damage: *damage,
+                    knockback: *knockback,
+                });
+                true
+            }
+        };
+        if spent {
+            consumed.insert(bullet_entity);
+            commands.entity(bullet_entity).despawn_recursive();
+        }
+    }
+}
+
+/// Zombies are steered by setting their velocity every tick, which would swallow an impulse,
+/// so they are pushed through their [Knockback] instead, every other dynamic body gets an [ExternalImpulse]
+fn explode(
+    mut commands: Commands,
+    rapier_context: Res<RapierContext>,
+    mut explosion_events: EventReader<ExplosionEvent>,
+    mut damage_events: EventWriter<DamageEvent>,
+    mut shake_events: EventWriter<CameraShakeEvent>,
+    mut zombie_query: Query<(&Transform, &mut Knockback), With<Zombie>>,
+    mut body_query: Query<
+        (
+            &Transform,
+            &RigidBody,
+            &ReadMassProperties,
+            &mut ExternalImpulse,
+        ),
+        Without<Knockback>,
+    >,
+) {
+    // the impulse is kept until it is changed again, so the first blast of a tick replaces the last one
+    let mut pushed = HashSet::default();
+    for explosion in explosion_events.iter() {
+        let blast = Collider::ball(explosion.radius);
+        let mut hit = Vec::new();
+        rapier_context.intersections_with_shape(
+            explosion.position,
+            0.,
+            &blast,
+            InteractionGroups::all(),
+            None,
+            |entity| {
+                hit.push(entity);
+                true
+            },
+        );
+
+        for entity in hit {
+            if let Ok((transform, mut knockback)) = zombie_query.get_mut(entity) {
+                let offset = transform.translation.truncate() - explosion.position;
+                let closeness = 1. - (offset.length() / explosion.radius).min(1.);
+                damage_events.send(DamageEvent {
+                    target: entity,
+                    source: explosion.source,
+                    amount: (explosion.damage as f32 * (0.5 + 0.5 * closeness)).round() as u32,
+                    position: transform.translation.truncate(),
+                });
+                knockback.velocity += offset.normalize_or_zero() * explosion.knockback * closeness;
+            } else if let Ok((transform, body, mass, mut impulse)) = body_query.get_mut(entity) {
+                if *body != RigidBody::Dynamic {
+                    continue;
+                }
+                let offset = transform.translation.truncate() - explosion.position;
+                let closeness = 1. - (offset.length() / explosion.radius).min(1.);
+                if pushed.insert(entity) {
+                    impulse.impulse = Vec2::ZERO;
+                }
+                impulse.impulse +=
+                    offset.normalize_or_zero() * explosion.knockback * closeness * mass.0.mass;
+            }
+        }
+        spawn_explosion_flash(&mut commands, explosion.position, explosion.radius);
+        shake_events.send(CameraShakeEvent { trauma: 0.3 });
+    }
+}
+
+/// Invulnerable players are left alone, so the hit and the knockback aren't repeated every tick of the contact
+fn zombie_contact_damage(
+    mut damage_events: EventWriter<DamageEvent>,
+    mut player_query: Query<
+        (Entity, &Transform, &mut Knockback),
+        (With<Player>, Without<Invulnerable>),
+    >,
+    zombie_query: Query<(Entity, &Transform, &Zombie)>,
+) {
+    let (player, player_transform, mut knockback) = match player_query.get_single_mut() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    let player_position = player_transform.translation.truncate();
+
+    for (zombie_entity, zombie_transform, zombie) in zombie_query.iter() {
+        let away = player_position - zombie_transform.translation.truncate();
+        if away.length() > PLAYER_RADIUS + zombie.radius {
+            continue;
+        }
+
+        damage_events.send(DamageEvent {
+            target: player,
+            source: zombie_entity,
+            amount: zombie.damage,
+            position: player_position,
+        });
+        knockback.velocity = away.normalize_or_zero() * PLAYER_KNOCKBACK_SPEED;
+        return;
+    }
+}
+
+fn tick_invulnerability(
+    mut commands: Commands,
+    time: Res<FixedTime>,
+    mut query: Query<(Entity, &mut Invulnerable, Option<&mut Sprite>)>,
+) {
+    for (entity, mut invulnerable, sprite) in query.iter_mut() {
+        invulnerable.0.tick(time.delta());
+        let finished = invulnerable.0.finished();
+        if let Some(mut sprite) = sprite {
+            // blink while invulnerable
+            let visible = finished || (invulnerable.0.elapsed_secs() * 10.) as u32 % 2 == 0;
+            sprite.color.set_a(if visible { 1. } else { 0.3 });
+        }
+        if finished {
+            commands.entity(entity).remove::<Invulnerable>();
+        }
+    }
+}
+
+/// Damage to [Invulnerable] entities is ignored, a player hit by a zombie becomes invulnerable for a moment
+fn apply_damage(
+    mut commands: Commands,
+    mut damage_events: EventReader<DamageEvent>,
+    mut taken_events: EventWriter<DamageTakenEvent>,
+    mut kill_events: EventWriter<KillEvent>,
+    mut health_query: Query<(&mut Health, Option<&Invulnerable>)>,
+    player_query: Query<(), With<Player>>,
+    zombie_query: Query<(), With<Zombie>>,
+) {
+    // the invulnerability is only inserted at the end of the tick, so remember who got it in this one
+    let mut made_invulnerable = HashSet::default();
+    for damage in damage_events.iter() {
+        if let Ok((mut health, invulnerable)) = health_query.get_mut(damage.target) {
+            if health.is_dead()
+                || invulnerable.is_some()
+                || made_invulnerable.contains(&damage.target)
+            {
+                continue;
+            }
+            let amount = damage.amount.min(health.current);
+            health.current -= amount;
+            taken_events.send(DamageTakenEvent {
+                target: damage.target,
+                source: damage.source,
+                amount,
+                position: damage.position,
+            });
+            if player_query.contains(damage.target) && zombie_query.contains(damage.source) {
+                made_invulnerable.insert(damage.target);
+                commands
+                    .entity(damage.target)
+                    .insert(Invulnerable::for_seconds(PLAYER_INVULNERABILITY));
+            }
+            if health.is_dead() {
+                kill_events.send(KillEvent {
+                    target: damage.target,
+                    source: damage.source,
+                    position: damage.position,
+                });
+            }
+        }
+    }
+}
+
+fn despawn_killed_zombies(
+    mut commands: Commands,
+    mut kill_events: EventReader<KillEvent>,
+    zombie_query: Query<(), With<Zombie>>,
+) {
+    for kill in kill_events.iter() {
+        if zombie_query.contains(kill.target) {
+            commands.entity(kill.target).despawn_recursive();
+        }
+    }
+}
+
+fn shake_on_hit(
+    mut damage_events: EventReader<DamageTakenEvent>,
+    mut shake_events: EventWriter<CameraShakeEvent>,
+) {
+    for _ in damage_events.iter() {
+        shake_events.send(CameraShakeEvent { trauma: 0.05 });
+    }
+}
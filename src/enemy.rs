@@ -0,0 +1,245 @@
+use crate::camera::CameraShakeEvent;
+use crate::combat::{CombatSystem, DamageEvent, Health, KillEvent, Knockback};
+use crate::player::Player;
+use crate::rng::{GameRng, RngStream};
+use crate::timestep::FixedUpdateAppExt;
+use crate::weapon::spawn_explosion_flash;
+use crate::{GameState, RunEntity, PIXELS_PER_METER};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy_prototype_lyon::prelude::{DrawMode, FillMode, FillOptions, GeometryBuilder};
+use bevy_prototype_lyon::shapes;
+use bevy_rapier2d::prelude::*;
+use rand::Rng;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+pub struct EnemyPlugin;
+
+/// This plugin runs the special behaviours of enemy archetypes and rolls their drop tables
+/// The archetypes themselves are defined in `assets/enemies` and spawned through [spawn_enemy]
+impl Plugin for EnemyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DropEvent>().add_fixed_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .with_system(detonate_exploders.before(CombatSystem))
+                .with_system(roll_drops.after(CombatSystem)),
+        );
+    }
+}
+
+/// All enemy archetypes by name
+#[derive(Deserialize, TypeUuid, Clone, Debug)]
+#[uuid = "9d2e7a41-3c58-4b1f-a6e0-5f8c2b7d4e19"]
+pub struct EnemyRoster {
+    pub enemies: HashMap<String, EnemyDef>,
+}
+
+impl EnemyRoster {
+    pub fn get(&self, name: &str) -> Option<&EnemyDef> {
+        self.enemies.get(name)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct EnemyDef {
+    pub radius: f32,
+    pub appearance: Appearance,
+    pub health: u32,
+    /// Movement speed in pixels per second
+    pub speed: f32,
+    pub mass: f32,
+    /// Damage dealt to the player on contact
+    pub damage: u32,
+    pub behaviour: Behaviour,
+    #[serde(default)]
+    pub drops: Vec<LootDrop>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum Appearance {
+    Color(Color),
+    /// Path of an image in the assets folder
+    Sprite(String),
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum Behaviour {
+    /// Follows the flow field towards the player
+    Chase,
+    /// Chases the player and blows up once it gets close enough
+    Exploder {
+        trigger_distance: f32,
+        blast_radius: f32,
+        blast_damage: u32,
+    },
+}
+
+/// An entry of a drop table
+#[derive(Deserialize, Clone, Debug)]
+pub struct LootDrop {
+    pub item: String,
+    /// Probability between 0 and 1 that the item drops
+    pub chance: f32,
+}
+
+#[derive(Component)]
+pub struct Zombie {
+    /// Name of the archetype in the [EnemyRoster]
+    pub kind: String,
+    /// Movement speed in pixels per second
+    pub speed: f32,
+    /// Damage dealt to the player on contact
+    pub damage: u32,
+    pub radius: f32,
+}
+
+#[derive(Component)]
+struct Exploder {
+    trigger_distance: f32,
+    blast_radius: f32,
+    blast_damage: u32,
+}
+
+#[derive(Component)]
+struct Drops(Vec<LootDrop>);
+
+/// Sent when a killed enemy drops an item
+pub struct DropEvent {
+    pub item: String,
+    pub position: Vec2,
+}
+
+pub fn spawn_enemy(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    name: &str,
+    def: &EnemyDef,
+    position: Vec2,
+) -> Entity {
+    let radius_in_meters = def.radius / PIXELS_PER_METER;
+    let density = def.mass / (PI * radius_in_meters * radius_in_meters);
+    let transform = Transform::from_translation(position.extend(0.));
+
+    let mut enemy = commands.spawn();
+    enemy
+        .insert(RigidBody::Dynamic)
+        .insert(GravityScale(0.))
+        .insert(Collider::ball(def.radius))
+        .insert(Restitution::coefficient(0.99))
+        .insert(Sleeping::disabled())
+        .insert(ColliderMassProperties::Density(density))
+        .insert(Velocity::default())
+        .insert(Zombie {
+            kind: name.to_string(),
+            speed: def.speed,
+            damage: def.damage,
+            radius: def.radius,
+        })
+        .insert(Health::new(def.health))
+        .insert(Knockback::default())
+        .insert(RunEntity);
+
+    match &def.appearance {
+        Appearance::Color(color) => {
+            enemy.insert_bundle(GeometryBuilder::build_as(
+                &shapes::Circle {
+                    radius: def.radius,
+                    center: Vec2::new(0., 0.),
+                },
+                DrawMode::Fill(FillMode {
+                    options: FillOptions::default(),
+                    color: *color,
+                }),
+                transform,
+            ));
+        }
+        Appearance::Sprite(path) => {
+            enemy.insert_bundle(SpriteBundle {
+                texture: asset_server.load(path.as_str()),
+                transform,
+                sprite: Sprite {
+                    custom_size: Some(Vec2::splat(def.radius * 2.)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            });
+        }
+    }
+
+    if let Behaviour::Exploder {
+        trigger_distance,
+        blast_radius,
+        blast_damage,
+    } = def.behaviour
+    {
+        enemy.insert(Exploder {
+            trigger_distance,
+            blast_radius,
+            blast_damage,
+        });
+    }
+    if !def.drops.is_empty() {
+        enemy.insert(Drops(def.drops.clone()));
+    }
+
+    enemy.id()
+}
+
+fn detonate_exploders(
+    mut commands: Commands,
+    mut damage_events: EventWriter<DamageEvent>,
+    mut shake_events: EventWriter<CameraShakeEvent>,
+    player_query: Query<(Entity, &Transform), With<Player>>,
+    exploder_query: Query<(Entity, &Transform, &Exploder)>,
+) {
+    let (player, player_transform) = match player_query.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    let player_position = player_transform.translation.truncate();
+
+    for (entity, transform, exploder) in exploder_query.iter() {
+        let distance = player_position.distance(transform.translation.truncate());
+        if distance > exploder.trigger_distance {
+            continue;
+        }
+        if distance <= exploder.blast_radius {
+            damage_events.send(DamageEvent {
+                target: player,
+                source: entity,
+                amount: exploder.blast_damage,
+                position: player_position,
+            });
+        }
+        spawn_explosion_flash(
+            &mut commands,
+            transform.translation.truncate(),
+            exploder.blast_radius,
+        );
+        shake_events.send(CameraShakeEvent { trauma: 0.4 });
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn roll_drops(
+    mut kill_events: EventReader<KillEvent>,
+    mut drop_events: EventWriter<DropEvent>,
+    mut game_rng: ResMut<GameRng>,
+    drops_query: Query<&Drops>,
+) {
+    let rng = game_rng.stream(RngStream::Drops);
+    for kill in kill_events.iter() {
+        if let Ok(drops) = drops_query.get(kill.target) {
+            for drop in drops.0.iter() {
+                if rng.gen::<f32>() < drop.chance {
+                    drop_events.send(DropEvent {
+                        item: drop.item.clone(),
+                        position: kill.position,
+                    });
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use crate::combat::Health;
+use crate::loading::FontAssets;
+use crate::player::Player;
+use crate::stats::RunStats;
+use crate::ui::{spawn_button, ButtonColors, ButtonPressedEvent, UiSystem};
+use crate::{log_state_error, GameState};
+use bevy::prelude::*;
+
+pub struct GameOverPlugin;
+
+/// This plugin ends the run once the player dies and shows the stats of the run
+/// From the game over screen a new run can be started or the player can go back to the menu
+impl Plugin for GameOverPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .with_system(end_run_on_player_death.label(GameOverSystem)),
+        )
+        .add_system_set(SystemSet::on_enter(GameState::GameOver).with_system(setup_game_over))
+        .add_system_set(
+            SystemSet::on_update(GameState::GameOver)
+                .with_system(press_game_over_button.after(UiSystem)),
+        )
+        .add_system_set(SystemSet::on_exit(GameState::GameOver).with_system(cleanup_game_over));
+    }
+}
+
+/// Label of the system ending the run, other systems changing the state during a run should run after it
+#[derive(Clone, Debug, PartialEq, Eq, Hash, SystemLabel)]
+pub struct GameOverSystem;
+
+#[derive(Component)]
+struct GameOverUi;
+
+#[derive(Component, Clone, Copy)]
+enum GameOverButton {
+    Restart,
+    Menu,
+}
+
+/// Checks the player's health instead of waiting for their [KillEvent](crate::combat::KillEvent),
+/// so a death isn't missed while the run is paused
+fn end_run_on_player_death(
+    player_query: Query<&Health, With<Player>>,
+    mut state: ResMut<State<GameState>>,
+) {
+    if player_query.iter().any(Health::is_dead) {
+        log_state_error(state.set(GameState::GameOver));
+    }
+}
+
+fn setup_game_over(
+    mut commands: Commands,
+    font_assets: Res<FontAssets>,
+    button_colors: Res<ButtonColors>,
+    stats: Res<RunStats>,
+) {
+    let text_style = |font_size: f32| TextStyle {
+        font: font_assets.fira_sans.clone(),
+        font_size,
+        color: Color::rgb(0.9, 0.9, 0.9),
+    };
+    let seconds = stats.time_survived as u32;
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: Color::rgba(0., 0., 0., 0.6).into(),
+            ..Default::default()
+        })
+        .insert(GameOverUi)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::with_section("Game Over", text_style(60.0), Default::default()),
+                ..Default::default()
+            });
+            for line in [
+                format!("Survived {}:{:02}", seconds / 60, seconds % 60),
+                format!("Score: {}", stats.score),
+                format!("Zombies killed: {}", stats.kills),
+                format!("Best kill streak: {}", stats.best_streak),
+                format!("Shots fired: {}", stats.shots_fired),
+                format!("Damage taken: {}", stats.damage_taken),
+            ] {
+                parent.spawn_bundle(TextBundle {
+                    style: Style {
+                        margin: Rect::all(Val::Px(4.0)),
+                        ..Default::default()
+                    },
+                    text: Text::with_section(line, text_style(30.0), Default::default()),
+                    ..Default::default()
+                });
+            }
+            for (button, label) in [
+                (GameOverButton::Restart, "Restart"),
+                (GameOverButton::Menu, "Menu"),
+            ] {
+                spawn_button(parent, &font_assets, &button_colors, label, 180.0).insert(button);
+            }
+        });
+}
+
+fn press_game_over_button(
+    mut pressed_events: EventReader<ButtonPressedEvent>,
+    button_query: Query<&GameOverButton>,
+    mut state: ResMut<State<GameState>>,
+) {
+    for ButtonPressedEvent(entity) in pressed_events.iter() {
+        let next = match button_query.get(*entity) {
+            Ok(GameOverButton::Restart) => GameState::Playing,
+            Ok(GameOverButton::Menu) => GameState::Menu,
+            Err(_) => continue,
+        };
+        log_state_error(state.set(next));
+    }
+}
+
+fn cleanup_game_over(mut commands: Commands, ui_query: Query<Entity, With<GameOverUi>>) {
+    for entity in ui_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
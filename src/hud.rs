@@ -0,0 +1,300 @@
+use crate::ability::Abilities;
+use crate::camera::ViewportSize;
+use crate::combat::Health;
+use crate::loading::FontAssets;
+use crate::player::Player;
+use crate::stats::RunStats;
+use crate::waves::{WaveDirector, WavePhase};
+use crate::weapon::Inventory;
+use crate::{GameState, RunEntity};
+use bevy::prelude::*;
+
+pub struct HudPlugin;
+
+/// This plugin shows the state of the run on top of the game during `GameState::Playing`
+/// Every part of the HUD is only rewritten when the data behind it changes,
+/// and the text is scaled with the window so it stays readable on small and large screens
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_enter(GameState::Playing).with_system(setup_hud))
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(show_health)
+                    .with_system(show_weapon)
+                    .with_system(show_abilities)
+                    .with_system(show_wave)
+                    .with_system(show_score),
+            )
+            // keeps scaling while paused, the HUD stays visible under the pause menu
+            .add_system(scale_hud_text);
+    }
+}
+
+/// Window height the font sizes of the HUD are chosen for
+const REFERENCE_HEIGHT: f32 = 800.;
+
+const TEXT_COLOR: Color = Color::rgb(0.95, 0.95, 0.95);
+const HEALTH_COLOR: Color = Color::rgb(0.8, 0.15, 0.15);
+const STREAK_COLOR: Color = Color::rgb(1., 0.75, 0.2);
+
+/// The part of the run a HUD text shows
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum HudField {
+    Health,
+    Weapon,
+    Abilities,
+    Wave,
+    Countdown,
+    Score,
+    Streak,
+}
+
+/// Font size of a HUD text at the reference window height
+#[derive(Component)]
+struct HudFontSize(f32);
+
+/// The filled part of the health bar
+#[derive(Component)]
+struct HealthBar;
+
+fn setup_hud(mut commands: Commands, font_assets: Res<FontAssets>) {
+    let text = |field: HudField, font_size: f32, color: Color| {
+        (
+            TextBundle {
+                text: Text::with_section(
+                    "",
+                    TextStyle {
+                        font: font_assets.fira_sans.clone(),
+                        font_size,
+                        color,
+                    },
+                    Default::default(),
+                ),
+                ..Default::default()
+            },
+            field,
+            HudFontSize(font_size),
+        )
+    };
+    let column = |align_items: AlignItems| NodeBundle {
+        style: Style {
+            flex_direction: FlexDirection::ColumnReverse,
+            align_items,
+            ..Default::default()
+        },
+        color: Color::NONE.into(),
+        ..Default::default()
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::SpaceBetween,
+                padding: Rect::all(Val::Percent(1.5)),
+                ..Default::default()
+            },
+            color: Color::NONE.into(),
+            ..Default::default()
+        })
+        .insert(RunEntity)
+        .with_children(|parent| {
+            // top row: player on the left, wave in the middle, score on the right
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Percent(100.0), Val::Auto),
+                        justify_content: JustifyContent::SpaceBetween,
+                        align_items: AlignItems::FlexEnd,
+                        ..Default::default()
+                    },
+                    color: Color::NONE.into(),
+                    ..Default::default()
+                })
+                .with_children(|parent| {
+                    parent
+                        .spawn_bundle(column(AlignItems::FlexStart))
+                        .with_children(|parent| {
+                            parent
+                                .spawn_bundle(NodeBundle {
+                                    style: Style {
+                                        size: Size::new(Val::Percent(100.0), Val::Auto),
+                                        min_size: Size::new(Val::Px(160.0), Val::Auto),
+                                        align_items: AlignItems::Center,
+                                        ..Default::default()
+                                    },
+                                    color: Color::rgba(0., 0., 0., 0.5).into(),
+                                    ..Default::default()
+                                })
+                                .with_children(|parent| {
+                                    parent
+                                        .spawn_bundle(NodeBundle {
+                                            style: Style {
+                                                size: Size::new(
+                                                    Val::Percent(100.0),
+                                                    Val::Percent(100.0),
+                                                ),
+                                                position_type: PositionType::Absolute,
+                                                ..Default::default()
+                                            },
+                                            color: HEALTH_COLOR.into(),
+                                            ..Default::default()
+                                        })
+                                        .insert(HealthBar);
+                                    parent.spawn_bundle(text(HudField::Health, 24., TEXT_COLOR));
+                                });
+                            parent.spawn_bundle(text(HudField::Weapon, 24., TEXT_COLOR));
+                        });
+                    parent
+                        .spawn_bundle(column(AlignItems::Center))
+                        .with_children(|parent| {
+                            parent.spawn_bundle(text(HudField::Wave, 32., TEXT_COLOR));
+                            parent.spawn_bundle(text(HudField::Countdown, 22., TEXT_COLOR));
+                        });
+                    parent
+                        .spawn_bundle(column(AlignItems::FlexEnd))
+                        .with_children(|parent| {
+                            parent.spawn_bundle(text(HudField::Score, 32., TEXT_COLOR));
+                            parent.spawn_bundle(text(HudField::Streak, 22., STREAK_COLOR));
+                        });
+                });
+            // bottom row: abilities
+            parent
+                .spawn_bundle(column(AlignItems::FlexStart))
+                .with_children(|parent| {
+                    parent.spawn_bundle(text(HudField::Abilities, 22., TEXT_COLOR));
+                });
+        });
+}
+
+/// Replaces the text of a HUD field, without touching it if nothing changed
+fn set_field(text_query: &mut Query<(&HudField, &mut Text)>, field: HudField, value: String) {
+    for (_, mut text) in text_query
+        .iter_mut()
+        .filter(|(text_field, _)| **text_field == field)
+    {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
+
+fn show_health(
+    player_query: Query<&Health, (With<Player>, Changed<Health>)>,
+    mut bar_query: Query<&mut Style, With<HealthBar>>,
+    mut text_query: Query<(&HudField, &mut Text)>,
+) {
+    let health = match player_query.get_single() {
+        Ok(health) => health,
+        Err(_) => return,
+    };
+    let fraction = health.current as f32 / health.max.max(1) as f32;
+    for mut style in bar_query.iter_mut() {
+        style.size.width = Val::Percent(fraction * 100.);
+    }
+    set_field(
+        &mut text_query,
+        HudField::Health,
+        format!(" {} / {}", health.current, health.max),
+    );
+}
+
+fn show_weapon(
+    player_query: Query<&Inventory, (With<Player>, Changed<Inventory>)>,
+    mut text_query: Query<(&HudField, &mut Text)>,
+) {
+    let inventory = match player_query.get_single() {
+        Ok(inventory) => inventory,
+        Err(_) => return,
+    };
+    let weapon = inventory.current();
+    let ammo = match weapon.reload_progress() {
+        Some(progress) => format!("reloading {:.0}%", progress * 100.),
+        None => format!("{} / {}", weapon.ammo, weapon.magazine_size),
+    };
+    let reserve = match weapon.reserve {
+        Some(reserve) => format!("  +{}", reserve),
+        None => String::new(),
+    };
+    set_field(
+        &mut text_query,
+        HudField::Weapon,
+        format!("{}  {}{}", weapon.kind.name(), ammo, reserve),
+    );
+}
+
+fn show_abilities(
+    player_query: Query<&Abilities, (With<Player>, Changed<Abilities>)>,
+    mut text_query: Query<(&HudField, &mut Text)>,
+) {
+    let abilities = match player_query.get_single() {
+        Ok(abilities) => abilities,
+        Err(_) => return,
+    };
+    let value = abilities
+        .iter()
+        .map(|ability| {
+            if ability.is_ready() {
+                format!("{} ready", ability.kind.name())
+            } else {
+                format!(
+                    "{} {:.1}s",
+                    ability.kind.name(),
+                    ability.remaining_cooldown()
+                )
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("   ");
+    set_field(&mut text_query, HudField::Abilities, value);
+}
+
+fn show_wave(director: Res<WaveDirector>, mut text_query: Query<(&HudField, &mut Text)>) {
+    if !director.is_changed() {
+        return;
+    }
+    let wave = match director.wave() {
+        0 => "Get ready".to_string(),
+        wave => format!("Wave {}", wave),
+    };
+    let countdown = match director.phase() {
+        WavePhase::Resting(_) => format!(
+            "Next wave in {:.0}",
+            director.countdown().unwrap_or_default().ceil()
+        ),
+        WavePhase::Spawning => format!("{} incoming", director.pending()),
+        WavePhase::Clearing => "Clear the wave".to_string(),
+    };
+    set_field(&mut text_query, HudField::Wave, wave);
+    set_field(&mut text_query, HudField::Countdown, countdown);
+}
+
+fn show_score(stats: Res<RunStats>, mut text_query: Query<(&HudField, &mut Text)>) {
+    if !stats.is_changed() {
+        return;
+    }
+    let streak = match stats.kill_streak {
+        0 | 1 => String::new(),
+        streak => format!("{} kill streak  x{}", streak, stats.streak_multiplier()),
+    };
+    set_field(&mut text_query, HudField::Score, format!("{}", stats.score));
+    set_field(&mut text_query, HudField::Streak, streak);
+}
+
+fn scale_hud_text(
+    viewport: Res<ViewportSize>,
+    mut text_query: Query<(&HudFontSize, &mut Text)>,
+    added_query: Query<(), Added<HudFontSize>>,
+) {
+    if !viewport.is_changed() && added_query.is_empty() {
+        return;
+    }
+    let scale = (viewport.0.y / REFERENCE_HEIGHT).clamp(0.6, 2.);
+    for (font_size, mut text) in text_query.iter_mut() {
+        for section in text.sections.iter_mut() {
+            section.style.font_size = font_size.0 * scale;
+        }
+    }
+}
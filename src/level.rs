@@ -0,0 +1,202 @@
+use crate::arena::{boundary_walls, ArenaLayout, Wall};
+use crate::player::PLAYER_COLLIDER_RADIUS;
+use crate::weapon::WeaponKind;
+use anyhow::{bail, Context};
+use bevy::asset::{AssetLoader, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::utils::BoxedFuture;
+use serde::Deserialize;
+
+/// A hand-authored arena, loaded from the `.level.ron` files in `assets/levels`
+#[derive(Deserialize, TypeUuid, Clone, Debug)]
+#[uuid = "5c8a0e13-7b4f-4d62-a1e9-3f2b6d8c4a75"]
+pub struct Level {
+    /// Shown in the menu
+    pub name: String,
+    pub floor: Floor,
+    /// Closed polygons in world coordinates, the floor is enclosed by walls automatically
+    pub walls: Vec<Vec<Vec2>>,
+    pub player_start: Vec2,
+    /// Where enemies enter the level
+    pub spawn_points: Vec<Vec2>,
+    pub pickups: Vec<LevelPickup>,
+}
+
+/// The walkable area, covered with tiles
+#[derive(Deserialize, Clone, Debug)]
+pub struct Floor {
+    pub min: Vec2,
+    pub max: Vec2,
+    pub tile_size: f32,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct LevelPickup {
+    /// Named like the items in an enemy's drop table
+    pub item: String,
+    pub position: Vec2,
+}
+
+impl Level {
+    /// Catches mistakes that would otherwise only show up while playing the level
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let floor = &self.floor;
+        if floor.min.cmpge(floor.max).any() || floor.tile_size <= 0. {
+            bail!("the floor is empty");
+        }
+        let on_floor = |point: Vec2| point.cmpge(floor.min).all() && point.cmple(floor.max).all();
+        if !on_floor(self.player_start) {
+            bail!("the player starts outside of the floor");
+        }
+        if self.spawn_points.is_empty() {
+            bail!("there are no spawn points");
+        }
+        if let Some(point) = self.spawn_points.iter().find(|point| !on_floor(**point)) {
+            bail!("spawn point {} is outside of the floor", point);
+        }
+        if let Some(index) = self.walls.iter().position(|wall| wall.len() < 3) {
+            bail!("wall {} has less than 3 points", index);
+        }
+        let walls: Vec<Wall> = self.walls.iter().cloned().map(Wall::Polygon).collect();
+        let in_wall =
+            |point: Vec2, radius: f32| walls.iter().any(|wall| wall.overlaps(point, radius));
+        if in_wall(self.player_start, PLAYER_COLLIDER_RADIUS) {
+            bail!("the player starts inside of a wall");
+        }
+        if let Some(point) = self.spawn_points.iter().find(|point| in_wall(**point, 0.)) {
+            bail!("spawn point {} is inside of a wall", point);
+        }
+        for pickup in self.pickups.iter() {
+            if WeaponKind::from_item(&pickup.item).is_none() {
+                bail!("unknown pickup '{}'", pickup.item);
+            }
+            if !on_floor(pickup.position) {
+                bail!("pickup '{}' is outside of the floor", pickup.item);
+            }
+            if in_wall(pickup.position, 0.) {
+                bail!("pickup '{}' is inside of a wall", pickup.item);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn layout(&self) -> ArenaLayout {
+        let mut walls: Vec<Wall> = self.walls.iter().cloned().map(Wall::Polygon).collect();
+        walls.extend(boundary_walls(self.floor.min, self.floor.max));
+        ArenaLayout {
+            min: self.floor.min,
+            max: self.floor.max,
+            tile_size: self.floor.tile_size,
+            walls,
+            player_start: self.player_start,
+            spawn_points: self.spawn_points.clone(),
+            pickups: self
+                .pickups
+                .iter()
+                .filter_map(|pickup| {
+                    WeaponKind::from_item(&pickup.item).map(|kind| (kind, pickup.position))
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Loads levels and validates them
+/// A level that doesn't parse or validate never finishes loading, which keeps the game in `GameState::Loading`,
+/// the tests check every level in `assets/levels` so this can't happen with the shipped ones
+#[derive(Default)]
+pub struct LevelLoader;
+
+impl AssetLoader for LevelLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let level = ron::de::from_bytes::<Level>(bytes)?;
+            level
+                .validate()
+                .with_context(|| format!("invalid level {}", load_context.path().display()))?;
+            load_context.set_default_asset(LoadedAsset::new(level));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["level.ron"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_levels_are_valid() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/levels");
+        let mut checked = 0;
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if !path.to_string_lossy().ends_with(".level.ron") {
+                continue;
+            }
+            let content = std::fs::read_to_string(&path).unwrap();
+            let level: Level = ron::from_str(&content)
+                .unwrap_or_else(|error| panic!("{} doesn't parse: {}", path.display(), error));
+            if let Err(error) = level.validate() {
+                panic!("{} is invalid: {:?}", path.display(), error);
+            }
+            checked += 1;
+        }
+        assert!(checked > 0, "found no levels in {}", dir);
+    }
+
+    fn level() -> Level {
+        Level {
+            name: "Test".to_string(),
+            floor: Floor {
+                min: Vec2::splat(-256.),
+                max: Vec2::splat(256.),
+                tile_size: 64.,
+            },
+            walls: vec![vec![
+                Vec2::new(64., 64.),
+                Vec2::new(192., 64.),
+                Vec2::new(192., 192.),
+                Vec2::new(64., 192.),
+            ]],
+            player_start: Vec2::ZERO,
+            spawn_points: vec![Vec2::new(-192., -192.)],
+            pickups: vec![LevelPickup {
+                item: "shotgun".to_string(),
+                position: Vec2::new(-128., 0.),
+            }],
+        }
+    }
+
+    #[test]
+    fn rejects_things_inside_of_walls() {
+        assert!(level().validate().is_ok());
+
+        let mut inside = level();
+        inside.player_start = Vec2::splat(128.);
+        assert!(inside.validate().is_err());
+
+        let mut inside = level();
+        inside.spawn_points.push(Vec2::splat(100.));
+        assert!(inside.validate().is_err());
+
+        let mut inside = level();
+        inside.pickups[0].position = Vec2::splat(150.);
+        assert!(inside.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_pickups_outside_of_the_floor() {
+        let mut outside = level();
+        outside.pickups[0].position = Vec2::new(0., -300.);
+        assert!(outside.validate().is_err());
+    }
+}
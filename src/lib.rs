@@ -1,42 +1,123 @@
+mod ability;
 mod actions;
+mod arena;
 mod audio;
+mod bindings;
+mod camera;
+mod combat;
+mod enemy;
+mod game_over;
+mod hud;
+mod level;
+mod lifetime;
 mod loading;
 mod menu;
+mod navigation;
+mod pause;
+mod pickup;
 mod player;
+mod replay;
+mod rng;
+mod settings;
+mod stats;
+mod timestep;
+mod ui;
+mod waves;
+mod weapon;
 
+use crate::ability::AbilityPlugin;
 use crate::actions::ActionsPlugin;
+use crate::arena::ArenaPlugin;
 use crate::audio::InternalAudioPlugin;
-use crate::loading::LoadingPlugin;
+use crate::camera::CameraControllerPlugin;
+use crate::combat::CombatPlugin;
+use crate::enemy::EnemyPlugin;
+use crate::game_over::GameOverPlugin;
+use crate::hud::HudPlugin;
+use crate::lifetime::LifetimePlugin;
+use crate::loading::{HeadlessLoadingPlugin, LoadingPlugin};
 use crate::menu::MenuPlugin;
+use crate::navigation::NavigationPlugin;
+use crate::pause::PausePlugin;
+use crate::pickup::PickupPlugin;
 use crate::player::PlayerPlugin;
+use crate::replay::ReplayPlugin;
+use crate::rng::RngPlugin;
+use crate::settings::SettingsPlugin;
+use crate::stats::StatsPlugin;
+use crate::timestep::TickPacing;
+use crate::ui::UiPlugin;
+use crate::waves::WavePlugin;
+use crate::weapon::WeaponPlugin;
 
 use bevy::app::App;
 
+use bevy::asset::AssetPlugin;
+use bevy::ecs::schedule::StateError;
+use bevy::hierarchy::HierarchyPlugin;
+use bevy::input::InputPlugin;
 use bevy::prelude::*;
+use bevy::window::WindowPlugin;
+
+pub use crate::actions::{ActionSource, Actions};
+pub use crate::camera::ViewportSize;
+pub use crate::combat::Health;
+pub use crate::enemy::Zombie;
+pub use crate::player::Player;
+pub use crate::replay::{Playback, Recording, Replay, ReplaySettings};
+pub use crate::rng::SeedMode;
+pub use crate::settings::Settings;
+pub use crate::timestep::{FixedTime, FixedTimestepPlugin};
+pub use crate::weapon::Bullet;
 
 // This example game uses States to separate logic
 // See https://bevy-cheatbook.github.io/programming/states.html
 // Or https://github.com/bevyengine/bevy/blob/main/examples/ecs/state.rs
 #[derive(Clone, Eq, PartialEq, Debug, Hash)]
-enum GameState {
+pub enum GameState {
     // During the loading State the LoadingPlugin will load our assets
     Loading,
     // During this State the actual game logic is executed
     Playing,
     // Here the menu is drawn and waiting for player interaction
     Menu,
+    // After the player died the stats of the run are shown until a new run is started
+    GameOver,
+    // Pushed on top of Playing, the run is frozen and the pause menu is shown
+    Paused,
+    // Pushed on top of Menu or Paused, the settings are changed here
+    Settings,
+}
+
+/// Logs a state change that couldn't be queued instead of panicking
+/// This happens when two systems change the state in the same frame, e.g. pausing while the player dies
+pub(crate) fn log_state_error(result: Result<(), StateError>) {
+    if let Err(error) = result {
+        warn!("Ignored a state change: {:?}", error);
+    }
 }
 
+/// Scale between the pixels of the world and the meters of the physics simulation
+pub const PIXELS_PER_METER: f32 = 25.0;
+
+/// Marks entities belonging to the current run
+/// They are despawned when leaving `GameState::Playing`, so a new run starts from a clean world
+#[derive(Component)]
+pub struct RunEntity;
+
 pub struct GamePlugin;
 
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
-        app.add_state(GameState::Loading)
+        app.add_plugin(SimulationPlugin)
             .add_plugin(LoadingPlugin)
+            .add_plugin(UiPlugin)
+            .add_plugin(SettingsPlugin)
             .add_plugin(MenuPlugin)
-            .add_plugin(ActionsPlugin)
             .add_plugin(InternalAudioPlugin)
-            .add_plugin(PlayerPlugin);
+            .add_plugin(GameOverPlugin)
+            .add_plugin(PausePlugin)
+            .add_plugin(HudPlugin);
 
         /*
         #[cfg(debug_assertions)]
@@ -47,3 +128,63 @@ impl Plugin for GamePlugin {
         */
     }
 }
+
+/// The game without a window, rendering, audio or UI, stepping one gameplay tick per app update
+/// It brings its own minimal set of bevy plugins, so it is added instead of `DefaultPlugins` and [GamePlugin]
+/// Tests drive the player by writing [Actions] directly and call `App::update` once per tick
+/// The world is sized by the [ViewportSize] resource, insert it before adding the plugin to change it
+/// A run starts as soon as the data assets are loaded and isn't ended when the player dies
+pub struct HeadlessGamePlugin;
+
+impl Plugin for HeadlessGamePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(TickPacing::OnePerUpdate)
+            .insert_resource(ActionSource::Scripted)
+            .add_plugins(MinimalPlugins)
+            .add_plugin(TransformPlugin)
+            .add_plugin(HierarchyPlugin)
+            .add_plugin(InputPlugin)
+            .add_plugin(WindowPlugin {
+                add_primary_window: false,
+                exit_on_close: false,
+            })
+            .add_plugin(AssetPlugin)
+            .add_plugin(FixedTimestepPlugin::physics())
+            .add_plugin(SimulationPlugin)
+            .add_plugin(HeadlessLoadingPlugin);
+    }
+}
+
+/// Everything that makes up a run of the game, shared by the windowed and the headless app
+struct SimulationPlugin;
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_state(GameState::Loading)
+            .add_plugin(FixedTimestepPlugin)
+            .add_plugin(RngPlugin)
+            .add_plugin(ReplayPlugin)
+            .add_plugin(ActionsPlugin)
+            .add_plugin(ArenaPlugin)
+            .add_plugin(PlayerPlugin)
+            .add_plugin(AbilityPlugin)
+            .add_plugin(CameraControllerPlugin)
+            .add_plugin(NavigationPlugin)
+            .add_plugin(CombatPlugin)
+            .add_plugin(StatsPlugin)
+            .add_plugin(WavePlugin)
+            .add_plugin(EnemyPlugin)
+            .add_plugin(WeaponPlugin)
+            .add_plugin(PickupPlugin)
+            .add_plugin(LifetimePlugin)
+            .add_system_set(
+                SystemSet::on_exit(GameState::Playing).with_system(despawn_run_entities),
+            );
+    }
+}
+
+fn despawn_run_entities(mut commands: Commands, query: Query<Entity, With<RunEntity>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
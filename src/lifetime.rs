@@ -0,0 +1,44 @@
+use crate::timestep::{FixedTime, FixedUpdateAppExt};
+use crate::GameState;
+use bevy::prelude::*;
+
+pub struct LifetimePlugin;
+
+/// This plugin despawns entities with a [Lifetime] once it runs out
+/// Lifetimes tick with the fixed gameplay tick during `GameState::Playing`, so they are frozen while the game is paused
+impl Plugin for LifetimePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_fixed_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .label(LifetimeSystem)
+                .with_system(despawn_expired),
+        );
+    }
+}
+
+/// Label of the system despawning expired entities
+/// Systems reacting to an entity expiring should run after it, the entity is still around until the end of the stage
+#[derive(Clone, Debug, PartialEq, Eq, Hash, SystemLabel)]
+pub struct LifetimeSystem;
+
+/// Despawns the entity once the timer finishes
+#[derive(Component, Debug)]
+pub struct Lifetime(pub Timer);
+
+impl Lifetime {
+    pub fn from_seconds(seconds: f32) -> Self {
+        Lifetime(Timer::from_seconds(seconds, false))
+    }
+}
+
+fn despawn_expired(
+    mut commands: Commands,
+    time: Res<FixedTime>,
+    mut lifetime_query: Query<(Entity, &mut Lifetime)>,
+) {
+    for (entity, mut lifetime) in lifetime_query.iter_mut() {
+        if lifetime.0.tick(time.delta()).just_finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
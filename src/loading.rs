@@ -1,7 +1,15 @@
+use crate::bindings::KeyBindings;
+use crate::enemy::EnemyRoster;
+use crate::level::{Level, LevelLoader};
+use crate::waves::WaveTable;
 use crate::GameState;
+use bevy::asset::{Asset, AssetLoader as BevyAssetLoader, LoadContext, LoadedAsset};
 use bevy::prelude::*;
+use bevy::utils::BoxedFuture;
 use bevy_asset_loader::{AssetCollection, AssetLoader};
 use bevy_kira_audio::AudioSource;
+use serde::de::DeserializeOwned;
+use std::marker::PhantomData;
 
 pub struct LoadingPlugin;
 
@@ -10,15 +18,51 @@ pub struct LoadingPlugin;
 /// If interested, take a look at https://bevy-cheatbook.github.io/features/assets.html
 impl Plugin for LoadingPlugin {
     fn build(&self, app: &mut App) {
+        add_data_assets(app);
+
         AssetLoader::new(GameState::Loading)
             .with_collection::<FontAssets>()
             .with_collection::<AudioAssets>()
             .with_collection::<TextureAssets>()
+            .with_collection::<BindingAssets>()
+            .with_collection::<WaveAssets>()
+            .with_collection::<EnemyAssets>()
+            .with_collection::<LevelAssets>()
             .continue_to_state(GameState::Menu)
             .build(app);
     }
 }
 
+pub struct HeadlessLoadingPlugin;
+
+/// This plugin loads only the data assets the simulation needs, fonts, audio and textures are skipped
+/// There is no menu in the headless app, so a run is started as soon as loading is done
+impl Plugin for HeadlessLoadingPlugin {
+    fn build(&self, app: &mut App) {
+        add_data_assets(app);
+
+        AssetLoader::new(GameState::Loading)
+            .with_collection::<BindingAssets>()
+            .with_collection::<WaveAssets>()
+            .with_collection::<EnemyAssets>()
+            .with_collection::<LevelAssets>()
+            .continue_to_state(GameState::Playing)
+            .build(app);
+    }
+}
+
+/// Registers the RON assets describing the game's data
+fn add_data_assets(app: &mut App) {
+    app.add_asset::<KeyBindings>()
+        .add_asset_loader(RonAssetLoader::<KeyBindings>::new(&["bindings.ron"]))
+        .add_asset::<WaveTable>()
+        .add_asset_loader(RonAssetLoader::<WaveTable>::new(&["waves.ron"]))
+        .add_asset::<EnemyRoster>()
+        .add_asset_loader(RonAssetLoader::<EnemyRoster>::new(&["enemies.ron"]))
+        .add_asset::<Level>()
+        .init_asset_loader::<LevelLoader>();
+}
+
 // the following asset collections will be loaded during the State `GameState::Loading`
 // when done loading, they will be inserted as resources (see https://github.com/NiklasEi/bevy_asset_loader)
 
@@ -43,3 +87,82 @@ pub struct TextureAssets {
     #[asset(path = "textures/grass.png")]
     pub texture_ground: Handle<Image>,
 }
+
+/// A texture out of the [TextureAssets], textures aren't loaded in the headless app so a default handle is used there
+pub fn texture_or_default(
+    textures: &Option<Res<TextureAssets>>,
+    pick: fn(&TextureAssets) -> &Handle<Image>,
+) -> Handle<Image> {
+    textures
+        .as_deref()
+        .map(|textures| pick(textures).clone())
+        .unwrap_or_default()
+}
+
+#[derive(AssetCollection)]
+pub struct BindingAssets {
+    #[asset(path = "controls/default.bindings.ron")]
+    pub key_bindings: Handle<KeyBindings>,
+}
+
+#[derive(AssetCollection)]
+pub struct WaveAssets {
+    #[asset(path = "waves/default.waves.ron")]
+    pub waves: Handle<WaveTable>,
+}
+
+#[derive(AssetCollection)]
+pub struct EnemyAssets {
+    #[asset(path = "enemies/default.enemies.ron")]
+    pub roster: Handle<EnemyRoster>,
+}
+
+/// The levels in `assets/levels`, a new level has to be added here to show up in the menu
+/// Loading the whole folder would be simpler, but folders can't be listed in the browser
+#[derive(AssetCollection)]
+pub struct LevelAssets {
+    #[asset(path = "levels/courtyard.level.ron")]
+    pub courtyard: Handle<Level>,
+    #[asset(path = "levels/pillars.level.ron")]
+    pub pillars: Handle<Level>,
+}
+
+impl LevelAssets {
+    pub fn levels(&self) -> impl Iterator<Item = Handle<Level>> + '_ {
+        [&self.courtyard, &self.pillars].into_iter().cloned()
+    }
+}
+
+/// Loads any deserializable asset from a RON file
+/// Every asset type gets its own compound extension (e.g. `bindings.ron`), so the loaders don't collide
+pub struct RonAssetLoader<T> {
+    extensions: &'static [&'static str],
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> RonAssetLoader<T> {
+    pub fn new(extensions: &'static [&'static str]) -> Self {
+        RonAssetLoader {
+            extensions,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Asset + DeserializeOwned> BevyAssetLoader for RonAssetLoader<T> {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let asset = ron::de::from_bytes::<T>(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(asset));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        self.extensions
+    }
+}
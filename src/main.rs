@@ -5,22 +5,38 @@ use bevy::prelude::*;
 use bevy::DefaultPlugins;
 use bevy_prototype_lyon::prelude::*;
 use bevy_rapier2d::prelude::*;
-use getting_over_him::GamePlugin;
+use getting_over_him::{FixedTimestepPlugin, GamePlugin, ReplaySettings, SeedMode, Settings};
 
 fn main() {
+    let mut warnings = Vec::new();
+    // the window is created from the settings, so they have to be read before building the app
+    let settings = Settings::load().unwrap_or_else(|error| {
+        warnings.push(format!(
+            "Failed to load the settings, using the defaults: {:?}",
+            error
+        ));
+        Settings::default()
+    });
+    let seed_mode = SeedMode::from_args(std::env::args()).unwrap_or_else(|error| {
+        warnings.push(format!("{}, using a random seed", error));
+        SeedMode::default()
+    });
     App::new()
-        .insert_resource(Msaa { samples: 4 })
+        .insert_resource(StartupWarnings(warnings))
+        .insert_resource(seed_mode)
+        .insert_resource(ReplaySettings::from_args(std::env::args()))
+        .insert_resource(settings.msaa())
         .insert_resource(ClearColor(Color::rgb(0.4, 0.4, 0.4)))
         .insert_resource(WindowDescriptor {
-            width: 1200.,
-            height: 800.,
             title: "Getting Over Him".to_string(),
-            ..Default::default()
+            ..settings.window_descriptor()
         })
+        .insert_resource(settings)
         .add_plugins(DefaultPlugins)
         .add_plugin(GamePlugin)
+        .add_startup_system(log_startup_warnings)
         .add_plugin(ShapePlugin)
-        .add_plugin(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(25.0))
+        .add_plugin(FixedTimestepPlugin::physics())
         .add_plugin(RapierDebugRenderPlugin {
             style: DebugRenderStyle {
                 border_subdivisions: 0,
@@ -44,3 +60,12 @@ fn main() {
         })
         .run();
 }
+
+/// Problems found while building the app, before anything could be logged
+struct StartupWarnings(Vec<String>);
+
+fn log_startup_warnings(warnings: Res<StartupWarnings>) {
+    for warning in warnings.0.iter() {
+        warn!("{}", warning);
+    }
+}
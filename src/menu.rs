@@ -1,90 +1,300 @@
-use crate::loading::FontAssets;
-use crate::GameState;
+use crate::arena::ArenaChoice;
+use crate::bindings::ControlInput;
+use crate::level::Level;
+use crate::loading::{FontAssets, LevelAssets};
+use crate::ui::{
+    spawn_button, ButtonColors, ButtonPressedEvent, Disabled, Focused, UiSystem, BACK,
+};
+use crate::{log_state_error, GameState};
+use bevy::app::AppExit;
 use bevy::prelude::*;
 
 pub struct MenuPlugin;
 
-/// This plugin is responsible for the game menu (containing only one button...)
+/// This plugin is responsible for the game menu, where the arena of the next run is chosen and the run started
+/// The menu is a stack of screens, going into a screen pushes it and "Back" pops it again
+/// The settings are their own state pushed on top of `GameState::Menu`, the menu is hidden meanwhile
 /// The menu is only drawn during the State `GameState::Menu` and is removed when that state is exited
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<ButtonColors>()
-            .add_system_set(SystemSet::on_enter(GameState::Menu).with_system(setup_menu))
-            .add_system_set(SystemSet::on_update(GameState::Menu).with_system(click_play_button));
+        app.init_resource::<MenuStack>()
+            .add_startup_system(spawn_ui_camera)
+            .add_system_set(SystemSet::on_enter(GameState::Menu).with_system(open_main_menu))
+            .add_system_set(
+                SystemSet::on_update(GameState::Menu)
+                    .with_system(press_menu_button.after(UiSystem))
+                    .with_system(go_back.after(press_menu_button))
+                    .with_system(show_menu_screen.after(go_back)),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::Menu).with_system(cleanup_menu))
+            .add_system_set(SystemSet::on_pause(GameState::Menu).with_system(cleanup_menu))
+            .add_system_set(SystemSet::on_resume(GameState::Menu).with_system(reopen_menu));
     }
 }
 
-struct ButtonColors {
-    normal: UiColor,
-    hovered: UiColor,
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MenuScreen {
+    Main,
+    LevelSelect,
+    Credits,
 }
 
-impl Default for ButtonColors {
+/// The open menu screens, only the last one is shown
+pub struct MenuStack(Vec<MenuScreen>);
+
+impl Default for MenuStack {
     fn default() -> Self {
-        ButtonColors {
-            normal: Color::rgb(0.15, 0.15, 0.15).into(),
-            hovered: Color::rgb(0.25, 0.25, 0.25).into(),
+        MenuStack(vec![MenuScreen::Main])
+    }
+}
+
+impl MenuStack {
+    pub fn current(&self) -> MenuScreen {
+        self.0.last().copied().unwrap_or(MenuScreen::Main)
+    }
+
+    pub fn push(&mut self, screen: MenuScreen) {
+        self.0.push(screen);
+    }
+
+    /// Goes back to the previous screen, the main screen is never popped
+    pub fn pop(&mut self) {
+        if self.0.len() > 1 {
+            self.0.pop();
+        }
+    }
+}
+
+#[derive(Component)]
+struct MenuUi;
+
+#[derive(Component, Clone, PartialEq)]
+enum MenuButton {
+    Play,
+    Open(MenuScreen),
+    Settings,
+    Back,
+    Quit,
+    /// Picks the arena of the next run and goes back
+    Arena(ArenaChoice),
+}
+
+const TITLE_SIZE: f32 = 60.0;
+const HEADING_SIZE: f32 = 36.0;
+const BODY_SIZE: f32 = 24.0;
+const CREDITS: &str = include_str!("../credits/CREDITS.md");
+
+fn spawn_ui_camera(mut commands: Commands) {
+    commands.spawn_bundle(UiCameraBundle::default());
+}
+
+fn open_main_menu(mut stack: ResMut<MenuStack>) {
+    *stack = MenuStack::default();
+}
+
+/// Shows the screen the player left when opening the settings
+fn reopen_menu(mut stack: ResMut<MenuStack>) {
+    stack.set_changed();
+}
+
+fn press_menu_button(
+    mut pressed_events: EventReader<ButtonPressedEvent>,
+    button_query: Query<&MenuButton>,
+    mut stack: ResMut<MenuStack>,
+    mut choice: ResMut<ArenaChoice>,
+    mut state: ResMut<State<GameState>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    for ButtonPressedEvent(entity) in pressed_events.iter() {
+        let button = match button_query.get(*entity) {
+            Ok(button) => button,
+            Err(_) => continue,
+        };
+        match button {
+            MenuButton::Play => log_state_error(state.set(GameState::Playing)),
+            MenuButton::Open(screen) => stack.push(*screen),
+            MenuButton::Settings => log_state_error(state.push(GameState::Settings)),
+            MenuButton::Back => stack.pop(),
+            MenuButton::Quit => exit.send(AppExit),
+            MenuButton::Arena(arena) => {
+                *choice = arena.clone();
+                stack.pop();
+            }
         }
     }
 }
 
-fn setup_menu(
+fn go_back(input: ControlInput, mut stack: ResMut<MenuStack>) {
+    if BACK.iter().any(|binding| input.just_pressed(binding)) {
+        stack.pop();
+    }
+}
+
+/// Rebuilds the menu whenever another screen is opened
+#[allow(clippy::too_many_arguments)]
+fn show_menu_screen(
     mut commands: Commands,
+    stack: Res<MenuStack>,
     font_assets: Res<FontAssets>,
     button_colors: Res<ButtonColors>,
+    choice: Res<ArenaChoice>,
+    level_assets: Res<LevelAssets>,
+    levels: Res<Assets<Level>>,
+    ui_query: Query<Entity, With<MenuUi>>,
 ) {
-    commands.spawn_bundle(UiCameraBundle::default());
+    if !stack.is_changed() {
+        return;
+    }
+    for entity in ui_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    let text = |value: &str, font_size: f32| TextBundle {
+        style: Style {
+            margin: Rect::all(Val::Px(4.0)),
+            ..Default::default()
+        },
+        text: Text::with_section(
+            value,
+            TextStyle {
+                font: font_assets.fira_sans.clone(),
+                font_size,
+                color: button_colors.text,
+            },
+            Default::default(),
+        ),
+        ..Default::default()
+    };
+
     commands
-        .spawn_bundle(ButtonBundle {
+        .spawn_bundle(NodeBundle {
             style: Style {
-                size: Size::new(Val::Px(120.0), Val::Px(50.0)),
-                margin: Rect::all(Val::Auto),
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
                 justify_content: JustifyContent::Center,
                 align_items: AlignItems::Center,
                 ..Default::default()
             },
-            color: button_colors.normal,
+            color: Color::NONE.into(),
             ..Default::default()
         })
+        .insert(MenuUi)
         .with_children(|parent| {
-            parent.spawn_bundle(TextBundle {
-                text: Text {
-                    sections: vec![TextSection {
-                        value: "Play".to_string(),
-                        style: TextStyle {
-                            font: font_assets.fira_sans.clone(),
-                            font_size: 40.0,
-                            color: Color::rgb(0.9, 0.9, 0.9),
-                        },
-                    }],
-                    alignment: Default::default(),
-                },
-                ..Default::default()
-            });
+            match stack.current() {
+                MenuScreen::Main => {
+                    parent.spawn_bundle(text("Getting Over Him", TITLE_SIZE));
+                    let arena = format!("Arena: {}", arena_name(&choice, &levels));
+                    for (button, label) in [
+                        (MenuButton::Play, "Play".to_string()),
+                        (MenuButton::Open(MenuScreen::LevelSelect), arena),
+                        (MenuButton::Settings, "Settings".to_string()),
+                        (MenuButton::Open(MenuScreen::Credits), "Credits".to_string()),
+                        (MenuButton::Quit, "Quit".to_string()),
+                    ] {
+                        let quit = button == MenuButton::Quit;
+                        let mut entity =
+                            spawn_button(parent, &font_assets, &button_colors, label, 360.0);
+                        entity.insert(button);
+                        // a browser tab can't be closed by the game
+                        if quit && cfg!(target_arch = "wasm32") {
+                            entity.insert(Disabled);
+                        }
+                    }
+                }
+                MenuScreen::LevelSelect => {
+                    parent.spawn_bundle(text("Level Select", TITLE_SIZE));
+                    for (name, arena) in arena_choices(&level_assets, &levels) {
+                        let selected = arena == *choice;
+                        let mut button =
+                            spawn_button(parent, &font_assets, &button_colors, name, 360.0);
+                        button.insert(MenuButton::Arena(arena));
+                        if selected {
+                            button.insert(Focused);
+                        }
+                    }
+                }
+                MenuScreen::Credits => {
+                    for (line, font_size) in credits_lines(CREDITS) {
+                        parent.spawn_bundle(text(&line, font_size));
+                    }
+                }
+            }
+            if stack.current() != MenuScreen::Main {
+                spawn_button(parent, &font_assets, &button_colors, "Back", 360.0)
+                    .insert(MenuButton::Back);
+            }
         });
 }
 
-fn click_play_button(
-    mut commands: Commands,
-    button_colors: Res<ButtonColors>,
-    mut state: ResMut<State<GameState>>,
-    mut interaction_query: Query<
-        (Entity, &Interaction, &mut UiColor),
-        (Changed<Interaction>, With<Button>),
-    >,
-) {
-    for (button, interaction, mut color) in interaction_query.iter_mut() {
-        match *interaction {
-            Interaction::Clicked => {
-                commands.entity(button).despawn_recursive();
-                state.set(GameState::Playing).unwrap();
-            }
-            Interaction::Hovered => {
-                *color = button_colors.hovered;
+fn arena_name<'a>(choice: &ArenaChoice, levels: &'a Assets<Level>) -> &'a str {
+    match choice {
+        ArenaChoice::Generated => "Random",
+        ArenaChoice::Level(handle) => levels
+            .get(handle)
+            .map_or("Random", |level| level.name.as_str()),
+    }
+}
+
+/// A generated arena followed by the levels in alphabetical order
+fn arena_choices(level_assets: &LevelAssets, levels: &Assets<Level>) -> Vec<(String, ArenaChoice)> {
+    let mut choices: Vec<(String, ArenaChoice)> = level_assets
+        .levels()
+        .filter_map(|handle| {
+            levels
+                .get(&handle)
+                .map(|level| (level.name.clone(), ArenaChoice::Level(handle)))
+        })
+        .collect();
+    choices.sort_by(|a, b| a.0.cmp(&b.0));
+    choices.insert(0, ("Random".to_string(), ArenaChoice::Generated));
+    choices
+}
+
+/// Turns the markdown of the credits into lines of plain text with their font size
+/// Only headings, list items and links are understood, which is all the credits use
+fn credits_lines(markdown: &str) -> Vec<(String, f32)> {
+    markdown
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            if let Some(heading) = line.strip_prefix("# ") {
+                (strip_links(heading), TITLE_SIZE)
+            } else if let Some(heading) = line.strip_prefix("## ") {
+                (strip_links(heading), HEADING_SIZE)
+            } else if let Some(item) = line.strip_prefix("* ").or_else(|| line.strip_prefix("- ")) {
+                (format!("• {}", strip_links(item)), BODY_SIZE)
+            } else {
+                (strip_links(line), BODY_SIZE)
             }
-            Interaction::None => {
-                *color = button_colors.normal;
+        })
+        .collect()
+}
+
+/// Replaces `[text](target)` with `text`
+fn strip_links(line: &str) -> String {
+    let mut plain = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find('[') {
+        let link = rest[start..].find("](").and_then(|middle| {
+            rest[start + middle..]
+                .find(')')
+                .map(|end| (middle, middle + end))
+        });
+        match link {
+            Some((middle, end)) => {
+                plain.push_str(&rest[..start]);
+                plain.push_str(&rest[start + 1..start + middle]);
+                rest = &rest[start + end + 1..];
             }
+            None => break,
         }
     }
+    plain.push_str(rest);
+    plain
+}
+
+fn cleanup_menu(mut commands: Commands, ui_query: Query<Entity, With<MenuUi>>) {
+    for entity in ui_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
 }
@@ -0,0 +1,287 @@
+use crate::camera::CameraBounds;
+use crate::combat::Knockback;
+use crate::enemy::Zombie;
+use crate::player::Player;
+use crate::timestep::{FixedTime, FixedUpdateAppExt};
+use crate::GameState;
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use bevy_rapier2d::prelude::*;
+use bevy_rapier2d::rapier::geometry::InteractionGroups;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+
+pub struct NavigationPlugin;
+
+/// This plugin keeps a flow field towards the player up to date and steers zombies along it
+/// The field is rebuilt when the player enters a new cell or fixed colliders are added or removed
+impl Plugin for NavigationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NavigationSettings>()
+            .init_resource::<FlowField>()
+            .add_fixed_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(rasterize_obstacles)
+                    .with_system(update_flow_field.after(rasterize_obstacles)),
+            )
+            .add_fixed_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .label(SteeringSystem)
+                    .after(update_flow_field)
+                    .with_system(steer_zombies),
+            );
+    }
+}
+
+/// Label of the system setting the zombies' velocities for the tick
+#[derive(Clone, Debug, PartialEq, Eq, Hash, SystemLabel)]
+pub struct SteeringSystem;
+
+pub struct NavigationSettings {
+    /// Edge length of a flow field cell in pixels
+    pub cell_size: f32,
+    /// Obstacles are grown by this radius, so agents don't get stuck on corners
+    pub agent_radius: f32,
+}
+
+impl Default for NavigationSettings {
+    fn default() -> Self {
+        NavigationSettings {
+            cell_size: 16.,
+            agent_radius: 8.,
+        }
+    }
+}
+
+/// A grid over the walkable area where every cell points to the neighbour closest to the player
+#[derive(Default)]
+pub struct FlowField {
+    origin: Vec2,
+    cell_size: f32,
+    size: IVec2,
+    blocked: Vec<bool>,
+    directions: Vec<Vec2>,
+    target: Option<IVec2>,
+}
+
+const NEIGHBOURS: [IVec2; 8] = [
+    IVec2::new(1, 0),
+    IVec2::new(-1, 0),
+    IVec2::new(0, 1),
+    IVec2::new(0, -1),
+    IVec2::new(1, 1),
+    IVec2::new(1, -1),
+    IVec2::new(-1, 1),
+    IVec2::new(-1, -1),
+];
+
+impl FlowField {
+    pub fn cell(&self, position: Vec2) -> Option<IVec2> {
+        if self.cell_size <= 0. {
+            return None;
+        }
+        let cell = ((position - self.origin) / self.cell_size)
+            .floor()
+            .as_ivec2();
+        self.contains(cell).then(|| cell)
+    }
+
+    pub fn cell_center(&self, cell: IVec2) -> Vec2 {
+        self.origin + (cell.as_vec2() + Vec2::splat(0.5)) * self.cell_size
+    }
+
+    /// Normalized direction towards the target, `None` outside of the field, in blocked cells
+    /// and in cells the target can't be reached from
+    pub fn direction(&self, position: Vec2) -> Option<Vec2> {
+        let cell = self.cell(position)?;
+        let direction = self.directions[self.index(cell)];
+        (direction != Vec2::ZERO).then(|| direction)
+    }
+
+    pub fn is_blocked(&self, cell: IVec2) -> bool {
+        !self.contains(cell) || self.blocked[self.index(cell)]
+    }
+
+    fn contains(&self, cell: IVec2) -> bool {
+        cell.x >= 0 && cell.y >= 0 && cell.x < self.size.x && cell.y < self.size.y
+    }
+
+    fn index(&self, cell: IVec2) -> usize {
+        (cell.y * self.size.x + cell.x) as usize
+    }
+
+    fn resize(&mut self, min: Vec2, max: Vec2, cell_size: f32) {
+        self.origin = min;
+        self.cell_size = cell_size;
+        self.size = ((max - min) / cell_size).ceil().as_ivec2().max(IVec2::ONE);
+        let cells = (self.size.x * self.size.y) as usize;
+        self.blocked = vec![false; cells];
+        self.directions = vec![Vec2::ZERO; cells];
+        self.target = None;
+    }
+
+    /// Runs a Dijkstra search outwards from the target cell and points every reachable cell
+    /// at its cheapest neighbour
+    fn build(&mut self, target: IVec2) {
+        self.target = Some(target);
+        let mut costs = vec![f32::INFINITY; self.blocked.len()];
+        self.directions
+            .iter_mut()
+            .for_each(|direction| *direction = Vec2::ZERO);
+        if self.is_blocked(target) {
+            return;
+        }
+
+        // the cost differences are small enough that a queue with re-relaxation is fast enough
+        let mut open = VecDeque::from([target]);
+        costs[self.index(target)] = 0.;
+        while let Some(cell) = open.pop_front() {
+            let cost = costs[self.index(cell)];
+            for offset in NEIGHBOURS {
+                let neighbour = cell + offset;
+                if self.is_blocked(neighbour) || self.cuts_corner(cell, offset) {
+                    continue;
+                }
+                let step = offset.as_vec2().length();
+                let index = self.index(neighbour);
+                if cost + step < costs[index] {
+                    costs[index] = cost + step;
+                    open.push_back(neighbour);
+                }
+            }
+        }
+
+        for y in 0..self.size.y {
+            for x in 0..self.size.x {
+                let cell = IVec2::new(x, y);
+                let index = self.index(cell);
+                if cell == target || !costs[index].is_finite() {
+                    continue;
+                }
+                let best = NEIGHBOURS
+                    .iter()
+                    .filter(|offset| {
+                        !self.is_blocked(cell + **offset) && !self.cuts_corner(cell, **offset)
+                    })
+                    .min_by(|a, b| {
+                        costs[self.index(cell + **a)]
+                            .partial_cmp(&costs[self.index(cell + **b)])
+                            .unwrap_or(Ordering::Equal)
+                    });
+                if let Some(offset) = best {
+                    self.directions[index] = offset.as_vec2().normalize();
+                }
+            }
+        }
+    }
+
+    /// Diagonal moves are only allowed if both adjacent straight cells are free
+    fn cuts_corner(&self, cell: IVec2, offset: IVec2) -> bool {
+        offset.x != 0
+            && offset.y != 0
+            && (self.is_blocked(cell + IVec2::new(offset.x, 0))
+                || self.is_blocked(cell + IVec2::new(0, offset.y)))
+    }
+}
+
+fn rasterize_obstacles(
+    mut flow_field: ResMut<FlowField>,
+    settings: Res<NavigationSettings>,
+    bounds: Option<Res<CameraBounds>>,
+    rapier_context: Res<RapierContext>,
+    added_colliders: Query<(Entity, Option<&RigidBody>), Added<Collider>>,
+    collider_query: Query<(), With<Collider>>,
+    mut obstacles: Local<HashSet<Entity>>,
+    mut pending: Local<bool>,
+) {
+    let mut changed = false;
+    for (entity, body) in added_colliders.iter() {
+        if matches!(body, None | Some(RigidBody::Fixed)) {
+            obstacles.insert(entity);
+            changed = true;
+        }
+    }
+    // removals are only tracked for one frame, which can pass without a tick, so look for gone colliders instead
+    let count = obstacles.len();
+    obstacles.retain(|entity| collider_query.contains(*entity));
+    changed |= obstacles.len() != count;
+
+    let bounds = match bounds {
+        Some(bounds) => bounds,
+        None => return,
+    };
+    // the physics world only picks up collider changes in its own stage, so rasterize one tick later
+    let rasterize = *pending;
+    *pending = changed || bounds.is_changed();
+    if !rasterize {
+        return;
+    }
+
+    flow_field.resize(bounds.min, bounds.max, settings.cell_size);
+    let probe = Collider::cuboid(
+        settings.cell_size / 2. + settings.agent_radius,
+        settings.cell_size / 2. + settings.agent_radius,
+    );
+    let is_obstacle = |entity: Entity| obstacles.contains(&entity);
+    for y in 0..flow_field.size.y {
+        for x in 0..flow_field.size.x {
+            let cell = IVec2::new(x, y);
+            let blocked = rapier_context
+                .intersection_with_shape(
+                    flow_field.cell_center(cell),
+                    0.,
+                    &probe,
+                    InteractionGroups::all(),
+                    Some(&is_obstacle),
+                )
+                .is_some();
+            let index = flow_field.index(cell);
+            flow_field.blocked[index] = blocked;
+        }
+    }
+}
+
+fn update_flow_field(
+    mut flow_field: ResMut<FlowField>,
+    player_query: Query<&Transform, With<Player>>,
+) {
+    let player_cell = match player_query.get_single() {
+        Ok(transform) => flow_field.cell(transform.translation.truncate()),
+        Err(_) => return,
+    };
+    if let Some(cell) = player_cell {
+        if flow_field.target != Some(cell) {
+            flow_field.build(cell);
+        }
+    }
+}
+
+fn steer_zombies(
+    time: Res<FixedTime>,
+    flow_field: Res<FlowField>,
+    player_query: Query<&Transform, (With<Player>, Without<Zombie>)>,
+    mut zombie_query: Query<
+        (&Transform, &Zombie, &mut Velocity, Option<&mut Knockback>),
+        Without<Player>,
+    >,
+) {
+    let player_position = match player_query.get_single() {
+        Ok(transform) => transform.translation.truncate(),
+        Err(_) => return,
+    };
+    let player_cell = flow_field.cell(player_position);
+
+    for (transform, zombie, mut velocity, knockback) in zombie_query.iter_mut() {
+        let position = transform.translation.truncate();
+        // in the player's cell, outside of the field or when cut off, head straight for the player
+        let direction = match flow_field.direction(position) {
+            Some(direction) if flow_field.cell(position) != player_cell => direction,
+            _ => (player_position - position).normalize_or_zero(),
+        };
+        velocity.linvel = direction * zombie.speed;
+        if let Some(mut knockback) = knockback {
+            velocity.linvel += knockback.velocity;
+            knockback.decay(time.delta_seconds());
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use crate::bindings::{ControlInput, GameControl, KeyBindings};
+use crate::game_over::GameOverSystem;
+use crate::loading::FontAssets;
+use crate::ui::{spawn_button, ButtonColors, ButtonPressedEvent, UiSystem};
+use crate::{log_state_error, GameState};
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+pub struct PausePlugin;
+
+/// This plugin pauses the run by pushing `GameState::Paused` on top of `GameState::Playing`
+/// All Playing systems and the physics simulation stop while paused and resuming pops the state again,
+/// so the `on_enter(GameState::Playing)` setup doesn't run a second time
+impl Plugin for PausePlugin {
+    fn build(&self, app: &mut App) {
+        // runs outside of the state system sets, so it can't see the key press again after the transition
+        // a run that just ended isn't paused, the game over screen is shown instead
+        app.add_system(toggle_pause.after(GameOverSystem))
+            .add_system_set(SystemSet::on_pause(GameState::Playing).with_system(freeze_run))
+            .add_system_set(SystemSet::on_resume(GameState::Playing).with_system(unfreeze_run))
+            .add_system_set(SystemSet::on_enter(GameState::Paused).with_system(setup_pause_menu))
+            .add_system_set(
+                SystemSet::on_update(GameState::Paused)
+                    .with_system(press_pause_button.after(UiSystem)),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::Paused).with_system(cleanup_pause_menu))
+            // the settings are pushed on top of the pause menu and replace it until they are closed
+            .add_system_set(SystemSet::on_pause(GameState::Paused).with_system(cleanup_pause_menu))
+            .add_system_set(SystemSet::on_resume(GameState::Paused).with_system(setup_pause_menu));
+    }
+}
+
+#[derive(Component)]
+struct PauseUi;
+
+#[derive(Component, Clone, Copy)]
+enum PauseButton {
+    Resume,
+    Settings,
+    QuitToMenu,
+}
+
+fn toggle_pause(
+    bindings: Res<KeyBindings>,
+    input: ControlInput,
+    mut state: ResMut<State<GameState>>,
+) {
+    if !bindings.just_pressed(GameControl::Pause, &input) {
+        return;
+    }
+    match state.current() {
+        GameState::Playing => log_state_error(state.push(GameState::Paused)),
+        GameState::Paused => log_state_error(state.pop()),
+        _ => {}
+    }
+}
+
+fn freeze_run(mut rapier_config: ResMut<RapierConfiguration>) {
+    rapier_config.physics_pipeline_active = false;
+}
+
+fn unfreeze_run(mut rapier_config: ResMut<RapierConfiguration>) {
+    rapier_config.physics_pipeline_active = true;
+}
+
+fn setup_pause_menu(
+    mut commands: Commands,
+    font_assets: Res<FontAssets>,
+    button_colors: Res<ButtonColors>,
+) {
+    let text_style = |font_size: f32, color: Color| TextStyle {
+        font: font_assets.fira_sans.clone(),
+        font_size,
+        color,
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: Color::rgba(0., 0., 0., 0.6).into(),
+            ..Default::default()
+        })
+        .insert(PauseUi)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::with_section(
+                    "Paused",
+                    text_style(60.0, Color::rgb(0.9, 0.9, 0.9)),
+                    Default::default(),
+                ),
+                ..Default::default()
+            });
+            for (button, label) in [
+                (PauseButton::Resume, "Resume"),
+                (PauseButton::Settings, "Settings"),
+                (PauseButton::QuitToMenu, "Quit to Menu"),
+            ] {
+                spawn_button(parent, &font_assets, &button_colors, label, 240.0).insert(button);
+            }
+        });
+}
+
+fn press_pause_button(
+    mut pressed_events: EventReader<ButtonPressedEvent>,
+    button_query: Query<&PauseButton>,
+    mut state: ResMut<State<GameState>>,
+) {
+    for ButtonPressedEvent(entity) in pressed_events.iter() {
+        match button_query.get(*entity) {
+            Ok(PauseButton::Resume) => log_state_error(state.pop()),
+            Ok(PauseButton::Settings) => log_state_error(state.push(GameState::Settings)),
+            // unwinds the stack, so leaving Playing despawns the run
+            Ok(PauseButton::QuitToMenu) => log_state_error(state.replace(GameState::Menu)),
+            Err(_) => {}
+        }
+    }
+}
+
+fn cleanup_pause_menu(mut commands: Commands, ui_query: Query<Entity, With<PauseUi>>) {
+    for entity in ui_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,118 @@
+use crate::arena::{ArenaLayout, ArenaSystem};
+use crate::combat::CombatSystem;
+use crate::enemy::DropEvent;
+use crate::lifetime::Lifetime;
+use crate::player::{Player, PLAYER_RADIUS};
+use crate::timestep::FixedUpdateAppExt;
+use crate::weapon::{Inventory, WeaponKind};
+use crate::{GameState, RunEntity};
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::{
+    DrawMode, FillMode, GeometryBuilder, RectangleOrigin, StrokeMode,
+};
+use bevy_prototype_lyon::shapes;
+
+pub struct PickupPlugin;
+
+/// This plugin places weapon pickups in the world and hands them to the player on contact
+/// Pickups lie around in the arena from the start of a run and are dropped by killed enemies
+impl Plugin for PickupPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_enter(GameState::Playing)
+                .after(ArenaSystem)
+                .with_system(spawn_starting_pickups),
+        )
+        .add_fixed_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .with_system(spawn_dropped_pickups.after(CombatSystem))
+                .with_system(collect_pickups),
+        );
+    }
+}
+
+#[derive(Component)]
+pub struct WeaponPickup {
+    pub kind: WeaponKind,
+}
+
+const PICKUP_SIZE: f32 = 12.;
+/// Seconds a pickup dropped by an enemy stays in the world
+const DROPPED_PICKUP_LIFETIME: f32 = 15.;
+
+fn pickup_color(kind: WeaponKind) -> Color {
+    match kind {
+        WeaponKind::Pistol => Color::WHITE,
+        WeaponKind::Shotgun => Color::ORANGE,
+        WeaponKind::Smg => Color::CYAN,
+        WeaponKind::Rifle => Color::PURPLE,
+        WeaponKind::Launcher => Color::LIME_GREEN,
+    }
+}
+
+pub fn spawn_pickup(
+    commands: &mut Commands,
+    kind: WeaponKind,
+    position: Vec2,
+    lifetime: Option<f32>,
+) -> Entity {
+    let mut pickup = commands.spawn_bundle(GeometryBuilder::build_as(
+        &shapes::Rectangle {
+            extents: Vec2::splat(PICKUP_SIZE),
+            origin: RectangleOrigin::Center,
+        },
+        DrawMode::Outlined {
+            fill_mode: FillMode::color(pickup_color(kind)),
+            outline_mode: StrokeMode::new(Color::BLACK, 2.),
+        },
+        Transform::from_translation(position.extend(0.5)),
+    ));
+    pickup.insert(WeaponPickup { kind }).insert(RunEntity);
+    // dropped pickups disappear after a while
+    if let Some(seconds) = lifetime {
+        pickup.insert(Lifetime::from_seconds(seconds));
+    }
+    pickup.id()
+}
+
+fn spawn_starting_pickups(mut commands: Commands, layout: Res<ArenaLayout>) {
+    for &(kind, position) in layout.pickups.iter() {
+        spawn_pickup(&mut commands, kind, position, None);
+    }
+}
+
+fn spawn_dropped_pickups(mut commands: Commands, mut drop_events: EventReader<DropEvent>) {
+    for drop in drop_events.iter() {
+        match WeaponKind::from_item(&drop.item) {
+            Some(kind) => {
+                spawn_pickup(
+                    &mut commands,
+                    kind,
+                    drop.position,
+                    Some(DROPPED_PICKUP_LIFETIME),
+                );
+            }
+            None => warn!("Enemy dropped unknown item '{}'", drop.item),
+        }
+    }
+}
+
+fn collect_pickups(
+    mut commands: Commands,
+    mut player_query: Query<(&Transform, &mut Inventory), With<Player>>,
+    pickup_query: Query<(Entity, &Transform, &WeaponPickup)>,
+) {
+    let (player_transform, mut inventory) = match player_query.get_single_mut() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    let player_position = player_transform.translation.truncate();
+
+    for (entity, transform, pickup) in pickup_query.iter() {
+        let distance = player_position.distance(transform.translation.truncate());
+        if distance <= PLAYER_RADIUS + PICKUP_SIZE / 2. {
+            inventory.collect(pickup.kind);
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
@@ -1,22 +1,27 @@
-use std::f32::consts::{FRAC_PI_2, PI, TAU};
-
-use crate::actions::Actions;
-use crate::loading::TextureAssets;
-use crate::GameState;
-use bevy::{
-    math::{Vec2Swizzles, Vec3Swizzles},
-    prelude::{shape::Quad, *},
-};
+use std::f32::consts::{FRAC_PI_2, PI};
+
+use crate::ability::{Abilities, AbilityKind, Dashing};
+use crate::actions::{Actions, ActionsSystem, TickActionsSystem};
+use crate::arena::{ArenaChoice, ArenaLayout, ArenaSystem};
+use crate::combat::{Health, Knockback};
+use crate::enemy::Zombie;
+use crate::loading::{texture_or_default, TextureAssets};
+use crate::navigation::SteeringSystem;
+use crate::rng::{GameRng, RngStream};
+use crate::timestep::{FixedTime, FixedUpdateAppExt, RenderInterpolation};
+use crate::weapon::{Inventory, Weapon, WeaponKind, BULLET_GROUP};
+use crate::{GameState, RunEntity};
+use bevy::prelude::{shape::Quad, *};
 use bevy_prototype_lyon::{
     prelude::{
         tess::geom::{euclid::Point2D, Point},
-        DrawMode, FillOptions, GeometryBuilder, Path, RectangleOrigin, ShapePath, StrokeMode,
-        StrokeOptions,
+        DrawMode, GeometryBuilder, Path, ShapePath, StrokeMode, StrokeOptions,
     },
     shapes::{self, Circle, Line},
 };
-
 use bevy_rapier2d::prelude::*;
+use bevy_rapier2d::rapier::geometry::InteractionGroups;
+
 use rand::Rng;
 
 pub struct PlayerPlugin;
@@ -24,93 +29,41 @@ pub struct PlayerPlugin;
 #[derive(Component)]
 pub struct Player;
 
+/// Distance from the player's center at which zombies touch the player
+pub const PLAYER_RADIUS: f32 = 24.;
+/// Radius of the player's collider, a bit smaller than [PLAYER_RADIUS] so zombies pressing against the player still touch it
+pub const PLAYER_COLLIDER_RADIUS: f32 = 20.;
+/// Physics group of the player, the player's own bullets pass through it
+pub const PLAYER_GROUP: u32 = 0b100;
+
 #[derive(Component, Default)]
 pub struct MousePos(Vec2);
 
-#[derive(Component, Debug)]
-pub struct Bullet {
-    pub lifetime: u32,
-}
-
 /// This plugin handles player related stuff like movement
+/// The player is a kinematic body, it is moved by shape casts against the walls and slides along them
 /// Player logic is only active during the State `GameState::Playing`
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set(
-            SystemSet::on_enter(GameState::Playing)
-                .with_system(setup_physics)
-                .with_system(spawn_player)
-                .with_system(spawn_camera),
-        )
-        .add_system_set(
-            SystemSet::on_update(GameState::Playing)
-                .with_system(move_player)
-                //.with_system(cursor_grab_system)
-                .with_system(shooting_system)
-                //.with_system(bullet_draw)
-                .with_system(bullet_delete_system)
-                .with_system(rotate_system)
-                .with_system(bullet_current_system)
-                .with_system(zombie_spawner)
-                .with_system(zombie_nav)
-                .with_system(zombie_despawn),
-        );
-    }
-}
-
-fn setup_physics(mut commands: Commands, textures: Res<TextureAssets>) {
-    let mut r = rand::thread_rng();
-
-    for i in -5..5 {
-        let a = r.gen_range(0.0..TAU);
-
-        commands
-            .spawn()
-            .insert(Collider::cuboid(100.0, 5.0))
-            .insert_bundle(GeometryBuilder::build_as(
-                &shapes::Rectangle {
-                    extents: Vec2::new(100.0 * 2., 5.0),
-                    origin: RectangleOrigin::Center,
-                },
-                DrawMode::Fill(bevy_prototype_lyon::prelude::FillMode {
-                    options: FillOptions::default(),
-                    color: Color::GRAY,
-                }),
-                Transform {
-                    translation: Vec3::new(100.0 * i as f32, -100.0, 0.0),
-                    rotation: Quat::from_rotation_z(a),
-                    ..Default::default()
-                },
-            ));
-    }
-
-    let wh_size = 64.;
-
-    for y in -10..10 {
-        for x in -10..10 {
-            commands
-                .spawn_bundle(SpriteBundle {
-                    texture: textures.texture_ground.clone(),
-                    transform: Transform::from_translation(Vec3::new(
-                        x as f32 * wh_size,
-                        y as f32 * wh_size,
-                        0.,
-                    )),
-                    sprite: Sprite {
-                        custom_size: Some(Vec2::new(wh_size, wh_size)),
-                        color: Color::rgba(0.5, 0.5, 0.5, 0.5),
-                        ..Default::default()
-                    },
-                    ..Default::default()
-                })
-                .insert(Tile);
-        }
+        app.init_resource::<MovementSettings>()
+            .add_system_set(
+                SystemSet::on_enter(GameState::Playing)
+                    .after(ArenaSystem)
+                    .with_system(spawn_player),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .after(ActionsSystem)
+                    //.with_system(cursor_grab_system)
+                    .with_system(rotate_system),
+            )
+            .add_fixed_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .after(TickActionsSystem)
+                    .with_system(move_player.after(SteeringSystem)),
+            );
     }
 }
 
-#[derive(Component)]
-struct Tile;
-
 #[allow(dead_code)]
 fn cursor_grab_system(mut windows: ResMut<Windows>, actions: Res<Actions>) {
     let window = windows.get_primary_mut().unwrap();
@@ -125,72 +78,6 @@ fn cursor_grab_system(mut windows: ResMut<Windows>, actions: Res<Actions>) {
     }
 }
 
-#[derive(Component)]
-struct Zombie {
-    health: u32,
-}
-
-fn zombie_spawner(
-    mut commands: Commands,
-    time: Res<Time>,
-    mut last_time: Local<f64>,
-    windows: Res<Windows>,
-) {
-    let lst = time.seconds_since_startup() - *last_time;
-    let window = windows.get_primary().unwrap();
-    let mut r = rand::thread_rng();
-    let a = r.gen_range(0.0..window.width());
-    if lst > 1.0 {
-        commands
-            .spawn()
-            .insert(RigidBody::Dynamic)
-            .insert(GravityScale(0.))
-            .insert(Collider::ball(8.0))
-            .insert(Restitution::coefficient(0.99))
-            .insert(Sleeping::disabled())
-            .insert(ColliderMassProperties::Density(5.0))
-            .insert_bundle(GeometryBuilder::build_as(
-                &shapes::Circle {
-                    radius: 8.0,
-                    center: Vec2::new(0., 0.),
-                },
-                DrawMode::Fill(bevy_prototype_lyon::prelude::FillMode {
-                    options: FillOptions::default(),
-                    color: Color::RED,
-                }),
-                Transform {
-                    translation: Vec3::new(a - window.width() / 2., -100., 0.0),
-                    ..Default::default()
-                },
-            ))
-            .insert(Zombie { health: 100 });
-        *last_time = time.seconds_since_startup();
-    }
-}
-
-fn zombie_nav(
-    player_query: Query<&Transform, (With<Player>, Without<Zombie>)>,
-    mut zombie_query: Query<&mut Transform, (With<Zombie>, Without<Player>)>,
-) {
-    let player_pos = player_query.single();
-    for mut zombie in zombie_query.iter_mut() {
-        let mut dir = player_pos.translation - zombie.translation;
-        dir = dir.normalize();
-        zombie.translation += dir * 0.5;
-    }
-}
-
-fn zombie_despawn(
-    mut commands: Commands,
-    mut zombie_query: Query<(Entity, &Zombie)>,
-) {
-    for (e, zombie) in zombie_query.iter_mut() {
-        if zombie.health <= 0 {
-            commands.entity(e).despawn_recursive();
-        }
-    }
-}
-
 pub trait Heading {
     fn heading(&self) -> f32;
 }
@@ -201,164 +88,312 @@ impl Heading for Vec2 {
     }
 }
 
-fn shooting_system(
-    mut commands: Commands,
-    actions: Res<Actions>,
-    mut player_query: Query<&mut Transform, With<Player>>,
-    windows: Res<Windows>,
-) {
-    if actions.trigger_pressed {
-        let window = windows.get_primary().unwrap();
-        let mut mouse_pos = Vec2::ZERO;
-
-        if let Some(_position) = window.cursor_position() {
-            // cursor is inside the window, position given
-            mouse_pos = _position;
-        }
-
-        let mut so = StrokeOptions::default();
-        so.line_width = 1.0;
-        let p = player_query.get_single();
-        if let Ok(player_transform) = p {
-            let player_pos = player_transform.translation;
-
-            let relative_mouse_world_pos =
-                mouse_pos - Vec2::new(window.width() / 2., window.height() / 2.);
-            let player_world_pos = relative_mouse_world_pos + player_pos.xy();
-            let heading = player_world_pos.y.atan2(player_world_pos.x);
-
-            let q = Quat::from_axis_angle(Vec3::Y, heading);
-            let mut v = (q * Vec2::new(player_world_pos.x, player_world_pos.y).extend(0.)).xy();
-
-            if relative_mouse_world_pos.x - player_pos.x < 0. {
-                v = Vec2::new(v.x * -1.0, v.y);
-            }
-
-            commands
-                .spawn()
-                .insert(RigidBody::Dynamic)
-                .insert(GravityScale(0.))
-                .insert(Collider::ball(0.5))
-                //.insert(ActiveCollisionTypes::DYNAMIC_KINEMATIC)
-                .insert(Restitution::coefficient(0.99))
-                .insert(Sleeping::disabled())
-                .insert(ColliderMassProperties::Density(1.0))
-                //.insert(Dominance::group(10))
-                .insert(Velocity {
-                    linvel: v * 5.,
-                    ..default()
-                })
-                .insert(Ccd::enabled())
-                .insert(Bullet {
-                    lifetime: 0,
-                })
-                .insert_bundle(TransformBundle::from_transform(Transform::from_translation(player_pos)));
-        }
-    }
-}
-
-fn bullet_current_system(mut bullet_query: Query<(&Velocity, &mut Bullet), With<Bullet>>) {
-    for (vel, mut bullet) in bullet_query.iter_mut() {
-        bullet.lifetime += 1;
-        if vel.linvel.length_squared() < 80. {
-            bullet.lifetime = 50;
-        }
-    }
-}
-
-fn bullet_delete_system(mut commands: Commands, bullet_query: Query<(Entity, &Bullet)>) {
-    for (e, bullet) in bullet_query.iter() {
-        if bullet.lifetime > 50 {
-            commands.entity(e).despawn_recursive();
-        }
-    }
-}
-
-fn spawn_camera(mut commands: Commands) {
-    commands.spawn_bundle(OrthographicCameraBundle::new_2d());
-}
-
 #[derive(Component)]
 struct LineShape;
 
-fn spawn_player(mut commands: Commands, textures: Res<TextureAssets>) {
+fn spawn_player(
+    mut commands: Commands,
+    textures: Option<Res<TextureAssets>>,
+    layout: Res<ArenaLayout>,
+    choice: Res<ArenaChoice>,
+    mut rng: ResMut<GameRng>,
+) {
+    let start = layout.player_start.extend(1.);
     commands
         .spawn_bundle(SpriteBundle {
-            texture: textures.texture_bevy.clone(),
-            transform: Transform::from_translation(Vec3::new(0., 0., 1.)),
+            texture: texture_or_default(&textures, |textures| &textures.texture_bevy),
+            transform: Transform::from_translation(start),
             sprite: Sprite {
                 custom_size: Some(Vec2::new(64., 64.)),
                 ..Default::default()
             },
             ..Default::default()
         })
-        .insert(Player);
+        .insert(Player)
+        .insert(RigidBody::KinematicPositionBased)
+        .insert(Collider::ball(PLAYER_COLLIDER_RADIUS))
+        .insert(CollisionGroups::new(PLAYER_GROUP, u32::MAX ^ BULLET_GROUP))
+        .insert(PlayerMotion::default())
+        .insert(Abilities::new(&[AbilityKind::Dash]))
+        .insert(Health::new(100))
+        .insert(Knockback::default())
+        .insert(RenderInterpolation::new(start))
+        .insert(Inventory::new(Weapon::new(WeaponKind::Pistol)))
+        .insert(RunEntity);
+
+    commands
+        .spawn()
+        .insert(MousePos(Vec2::new(0.0, 0.0)))
+        .insert(RunEntity);
 
-    commands.spawn().insert(MousePos(Vec2::new(0.0, 0.0)));
+    // the lines would cross the walls of a hand-made level
+    if *choice != ArenaChoice::Generated {
+        return;
+    }
 
     let mut so = StrokeOptions::default();
     so.line_width = 3.0;
 
-    let mut r = rand::thread_rng();
+    let r = rng.stream(RngStream::World);
 
     for i in -10..10 {
         let w = r.gen_range(0.0..100.);
         let h = r.gen_range(0.0..100.);
         let w1 = r.gen_range(0.0..100.);
         let h1 = r.gen_range(0.0..100.);
-        let lines = shapes::Line(Vec2::new(w * i as f32, h * i as f32), Vec2::new(w1 * i as f32, h1 * i as f32));
+        let lines = shapes::Line(
+            Vec2::new(w * i as f32, h * i as f32),
+            Vec2::new(w1 * i as f32, h1 * i as f32),
+        );
         commands
-        .spawn_bundle(GeometryBuilder::build_as(
-            &lines,
-            DrawMode::Stroke(StrokeMode {
-                options: so,
-                color: Color::BLACK,
-            }),
-            Transform::default(),
-        ))
-        .insert(LineShape);
+            .spawn_bundle(GeometryBuilder::build_as(
+                &lines,
+                DrawMode::Stroke(StrokeMode {
+                    options: so,
+                    color: Color::BLACK,
+                }),
+                Transform::default(),
+            ))
+            .insert(LineShape)
+            .insert(RunEntity);
+    }
+}
+
+fn rotate_system(mut query: Query<&mut Transform, With<Player>>, actions: Res<Actions>) {
+    if let Some(aim_direction) = actions.aim_direction {
+        for mut transform in query.iter_mut() {
+            transform.rotation = Quat::from_rotation_z(aim_direction.heading() - FRAC_PI_2);
+        }
     }
 }
 
-fn rotate_system(mut query: Query<(&mut Transform, With<Player>)>, windows: Res<Windows>) {
-    let window = windows.get_primary().unwrap();
-    let mut delta_mouse_pos = Vec2::ZERO;
+/// How the player speeds up and slows down
+pub struct MovementSettings {
+    /// Top speed in pixels per second
+    pub max_speed: f32,
+    /// Speed gained per second when starting to move or changing direction
+    pub acceleration: f32,
+    /// Fraction of the acceleration lost when already moving at top speed in the wanted direction
+    pub acceleration_falloff: f32,
+    /// Speed lost per second without movement input
+    pub friction: f32,
+    /// Fraction of the speed lost per second without movement input, on top of the friction
+    pub drag: f32,
+    /// Fraction of a zombie's speed towards the player that is passed on to the player
+    pub zombie_push: f32,
+}
 
-    if let Some(_position) = window.cursor_position() {
-        delta_mouse_pos = _position;
+impl Default for MovementSettings {
+    fn default() -> Self {
+        MovementSettings {
+            max_speed: 150.,
+            acceleration: 1400.,
+            acceleration_falloff: 0.6,
+            friction: 500.,
+            drag: 4.,
+            zombie_push: 0.6,
+        }
     }
+}
 
-    for (mut transform, is_rotation_entity) in query.iter_mut() {
-        let player_pos = transform.translation;
+/// The velocity the player moves at by their own intent, knockback and pushes are added on top
+#[derive(Component, Default, Debug)]
+pub struct PlayerMotion {
+    pub velocity: Vec2,
+}
 
-        let relative_mouse_world_pos =
-            delta_mouse_pos - Vec2::new(window.width() / 2., window.height() / 2.);
-        let player_world_pos = relative_mouse_world_pos + player_pos.xy();
-        let heading = player_world_pos.y.atan2(player_world_pos.x);
+/// Accelerates towards the velocity given by the movement input, or slows down without input
+fn accelerate(
+    velocity: Vec2,
+    input: Vec2,
+    settings: &MovementSettings,
+    delta_seconds: f32,
+) -> Vec2 {
+    if input == Vec2::ZERO {
+        let speed = velocity.length();
+        let slowed = (speed - (settings.friction + settings.drag * speed) * delta_seconds).max(0.);
+        return velocity.normalize_or_zero() * slowed;
+    }
+    let target = input * settings.max_speed;
+    // how much of the target velocity is already reached, turning around gets the full acceleration
+    let reached = (velocity.dot(target) / target.length_squared()).clamp(0., 1.);
+    let acceleration = settings.acceleration * (1. - settings.acceleration_falloff * reached);
+    velocity + (target - velocity).clamp_length_max(acceleration * delta_seconds)
+}
+
+/// Gap kept between a moving shape and the walls, so it doesn't start the next cast inside of them
+const SKIN_WIDTH: f32 = 0.5;
+/// How often a movement may be deflected by walls within one tick
+const MAX_SLIDES: usize = 4;
+
+/// Where a shape ends up after [move_and_slide]
+pub struct Slide {
+    pub position: Vec2,
+    /// Normal of the last wall the shape ran into
+    pub normal: Option<Vec2>,
+}
+
+/// Moves a shape by shape casts, at every wall in the way the rest of the movement is turned along the wall
+/// Only colliders accepted by `is_wall` stop the shape
+pub fn move_and_slide(
+    rapier_context: &RapierContext,
+    shape: &Collider,
+    position: Vec2,
+    motion: Vec2,
+    is_wall: &dyn Fn(Entity) -> bool,
+) -> Slide {
+    let mut slide = Slide {
+        position,
+        normal: None,
+    };
+    let mut remaining = motion;
+    for _ in 0..MAX_SLIDES {
+        let length = remaining.length();
+        if length <= f32::EPSILON {
+            break;
+        }
+        let hit = rapier_context.cast_shape(
+            slide.position,
+            0.,
+            remaining,
+            shape,
+            1.,
+            InteractionGroups::all(),
+            Some(is_wall),
+        );
+        let (_, toi) = match hit {
+            Some(hit) => hit,
+            None => {
+                slide.position += remaining;
+                break;
+            }
+        };
+        // the normal on the cast shape points into the wall, flipped it is the wall's normal
+        let normal = -toi.normal2;
+        let travel = (toi.toi * length - SKIN_WIDTH).max(0.);
+        slide.position += remaining / length * travel;
+        slide.normal = Some(normal);
+
+        let rest = remaining * (1. - toi.toi);
+        remaining = rest - normal * rest.dot(normal).min(0.);
+    }
+    slide
+}
 
-        if is_rotation_entity {
-            transform.rotation = Quat::from_rotation_z(heading - FRAC_PI_2);
+/// Colliders without a body or with a fixed one are walls, like in the navigation
+pub fn is_wall(body_query: &Query<Option<&RigidBody>>, entity: Entity) -> bool {
+    matches!(body_query.get(entity), Ok(None | Some(RigidBody::Fixed)))
+}
+
+/// Zombies walking into the player push the player along
+fn zombie_push<'a>(
+    position: Vec2,
+    zombies: impl Iterator<Item = (&'a Transform, &'a Zombie, &'a Velocity)>,
+    push: f32,
+) -> Vec2 {
+    let mut velocity = Vec2::ZERO;
+    for (transform, zombie, zombie_velocity) in zombies {
+        let offset = position - transform.translation.truncate();
+        if offset.length() > PLAYER_RADIUS + zombie.radius {
+            continue;
         }
+        let direction = offset.normalize_or_zero();
+        velocity += direction * zombie_velocity.linvel.dot(direction).max(0.) * push;
     }
+    velocity
 }
 
-#[allow(unused_mut)]
 fn move_player(
-    time: Res<Time>,
+    time: Res<FixedTime>,
     actions: Res<Actions>,
-    mut player_query: Query<&mut Transform, With<Player>>,
+    settings: Res<MovementSettings>,
+    rapier_context: Res<RapierContext>,
+    body_query: Query<Option<&RigidBody>>,
+    zombie_query: Query<(&Transform, &Zombie, &Velocity), Without<Player>>,
+    mut player_query: Query<
+        (&mut Transform, &mut PlayerMotion, &mut Knockback, &Collider),
+        (With<Player>, Without<Dashing>),
+    >,
 ) {
-    if actions.player_movement.is_none() {
-        return;
+    let delta_seconds = time.delta_seconds();
+    let input = actions
+        .player_movement
+        .unwrap_or(Vec2::ZERO)
+        .clamp_length_max(1.);
+    let is_wall = |entity| is_wall(&body_query, entity);
+
+    for (mut transform, mut motion, mut knockback, collider) in player_query.iter_mut() {
+        let position = transform.translation.truncate();
+        motion.velocity = accelerate(motion.velocity, input, &settings, delta_seconds);
+        let push = zombie_push(position, zombie_query.iter(), settings.zombie_push);
+        let velocity = motion.velocity + knockback.velocity + push;
+
+        let slide = move_and_slide(
+            &rapier_context,
+            collider,
+            position,
+            velocity * delta_seconds,
+            &is_wall,
+        );
+        transform.translation = slide.position.extend(transform.translation.z);
+        // running into a wall doesn't keep the speed pointing into it
+        if let Some(normal) = slide.normal {
+            motion.velocity -= normal * motion.velocity.dot(normal).min(0.);
+        }
+        knockback.decay(delta_seconds);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> MovementSettings {
+        MovementSettings {
+            max_speed: 100.,
+            acceleration: 1000.,
+            acceleration_falloff: 0.5,
+            friction: 200.,
+            drag: 1.,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn acceleration_falls_off_towards_top_speed() {
+        let settings = settings();
+        let velocity = accelerate(Vec2::ZERO, Vec2::X, &settings, 0.05);
+        assert!(velocity.abs_diff_eq(Vec2::new(50., 0.), 1e-3));
+        // half of the top speed is reached, so a quarter of the acceleration is lost
+        let velocity = accelerate(velocity, Vec2::X, &settings, 0.05);
+        assert!(velocity.abs_diff_eq(Vec2::new(87.5, 0.), 1e-3));
+    }
+
+    #[test]
+    fn turning_around_gets_the_full_acceleration() {
+        let velocity = accelerate(Vec2::new(100., 0.), -Vec2::X, &settings(), 0.05);
+        assert!(velocity.abs_diff_eq(Vec2::new(50., 0.), 1e-3));
+    }
+
+    #[test]
+    fn never_exceeds_max_speed() {
+        let settings = settings();
+        let input = Vec2::new(1., 1.).normalize();
+        let mut velocity = Vec2::new(-80., 30.);
+        for _ in 0..120 {
+            velocity = accelerate(velocity, input, &settings, 1. / 60.);
+            assert!(velocity.length() <= settings.max_speed + 1e-3);
+        }
+        assert!(velocity.abs_diff_eq(input * settings.max_speed, 1e-3));
     }
 
-    let speed = 150.;
-    let movement = Vec3::new(
-        actions.player_movement.unwrap().x * speed * time.delta_seconds(),
-        actions.player_movement.unwrap().y * speed * time.delta_seconds(),
-        0.,
-    );
-    for mut player_transform in player_query.iter_mut() {
-        player_transform.translation += movement;
+    #[test]
+    fn friction_stops_without_reversing() {
+        let settings = settings();
+        // friction and drag of the current speed together
+        let velocity = accelerate(Vec2::new(0., 100.), Vec2::ZERO, &settings, 0.1);
+        assert!(velocity.abs_diff_eq(Vec2::new(0., 70.), 1e-3));
+        let velocity = accelerate(Vec2::new(0., 10.), Vec2::ZERO, &settings, 0.1);
+        assert_eq!(velocity, Vec2::ZERO);
     }
 }
@@ -0,0 +1,498 @@
+use crate::actions::{ActionSource, Actions, TickActionsSystem, WeaponSelection};
+use crate::arena::ArenaChoice;
+use crate::rng::{GameRng, RngSystem, SeedMode};
+use crate::timestep::FixedUpdateAppExt;
+use crate::GameState;
+use anyhow::{bail, Context};
+use bevy::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub struct ReplayPlugin;
+
+/// This plugin records the [Actions] of every fixed tick of a run together with the run's seed and level
+/// A recording is written to the file given with `--record <path>` when the run ends,
+/// and `--replay <path>` feeds a recorded run back into [Actions] instead of reading the input devices
+/// The game inserts the [ReplaySettings] from the command line, without them nothing is recorded or replayed
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ReplaySettings>()
+            .init_resource::<Recording>()
+            .add_startup_system(load_playback)
+            .add_system_set(
+                SystemSet::on_enter(GameState::Playing)
+                    .with_system(start_playback.before(RngSystem))
+                    .with_system(start_recording.after(RngSystem)),
+            )
+            .add_fixed_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .label(TickActionsSystem)
+                    .with_system(play_tick)
+                    .with_system(record_tick.after(play_tick)),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::Playing).with_system(finish_run));
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct ReplaySettings {
+    pub record_to: Option<PathBuf>,
+    pub play_from: Option<PathBuf>,
+}
+
+impl ReplaySettings {
+    /// Reads `--record <path>` and `--replay <path>` from the command line arguments
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Self {
+        let mut args = args.into_iter();
+        let mut settings = ReplaySettings::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--record" => settings.record_to = args.next().map(PathBuf::from),
+                "--replay" => settings.play_from = args.next().map(PathBuf::from),
+                _ => {}
+            }
+        }
+        settings
+    }
+}
+
+/// The player intent of a single fixed tick
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TickActions {
+    pub player_movement: Option<Vec2>,
+    pub aim_target: Option<Vec2>,
+    pub aim_direction: Option<Vec2>,
+    pub trigger_pressed: bool,
+    pub reload_pressed: bool,
+    pub dash_pressed: bool,
+    pub weapon_selection: Option<WeaponSelection>,
+}
+
+impl TickActions {
+    pub fn from_actions(actions: &Actions) -> Self {
+        TickActions {
+            player_movement: actions.player_movement,
+            aim_target: actions.aim_target,
+            aim_direction: actions.aim_direction,
+            trigger_pressed: actions.trigger_pressed,
+            reload_pressed: actions.reload_pressed,
+            dash_pressed: actions.dash_pressed,
+            weapon_selection: actions.weapon_selection,
+        }
+    }
+
+    pub fn apply(&self, actions: &mut Actions) {
+        actions.player_movement = self.player_movement;
+        actions.aim_target = self.aim_target;
+        actions.aim_direction = self.aim_direction;
+        actions.trigger_pressed = self.trigger_pressed;
+        actions.reload_pressed = self.reload_pressed;
+        actions.dash_pressed = self.dash_pressed;
+        actions.weapon_selection = self.weapon_selection;
+    }
+}
+
+/// A recorded run, everything needed to play it again
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Replay {
+    pub seed: u64,
+    /// Asset path of the level the run was played in, `None` for a generated arena
+    pub level: Option<String>,
+    pub ticks: Vec<TickActions>,
+}
+
+const MAGIC: &[u8; 4] = b"GOHR";
+const FORMAT_VERSION: u8 = 3;
+
+const HAS_MOVEMENT: u16 = 1 << 0;
+const HAS_AIM_TARGET: u16 = 1 << 1;
+const HAS_AIM_DIRECTION: u16 = 1 << 2;
+const TRIGGER_PRESSED: u16 = 1 << 3;
+const RELOAD_PRESSED: u16 = 1 << 4;
+const SELECTION_SHIFT: u16 = 5;
+const SELECTION_MASK: u16 = 0b111;
+const DASH_PRESSED: u16 = 1 << 8;
+
+impl Replay {
+    pub fn new(seed: u64) -> Self {
+        Replay {
+            seed,
+            level: None,
+            ticks: Vec::new(),
+        }
+    }
+
+    /// Encodes the replay, runs of identical ticks are stored only once
+    ///
+    /// Layout (little endian): magic, version, seed, length and bytes of the level path, number of runs,
+    /// then per run the repeat count, the flags and the vectors present according to the flags
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut runs: Vec<(u16, &TickActions)> = Vec::new();
+        for tick in self.ticks.iter() {
+            match runs.last_mut() {
+                Some((count, last)) if *last == tick && *count < u16::MAX => *count += 1,
+                _ => runs.push((1, tick)),
+            }
+        }
+
+        let level = self.level.as_deref().unwrap_or_default().as_bytes();
+        let mut bytes = Vec::with_capacity(19 + level.len() + runs.len() * 4);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(FORMAT_VERSION);
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.extend_from_slice(&(level.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(level);
+        bytes.extend_from_slice(&(runs.len() as u32).to_le_bytes());
+        for (count, tick) in runs {
+            bytes.extend_from_slice(&count.to_le_bytes());
+            let mut flags = 0;
+            if tick.player_movement.is_some() {
+                flags |= HAS_MOVEMENT;
+            }
+            if tick.aim_target.is_some() {
+                flags |= HAS_AIM_TARGET;
+            }
+            if tick.aim_direction.is_some() {
+                flags |= HAS_AIM_DIRECTION;
+            }
+            if tick.trigger_pressed {
+                flags |= TRIGGER_PRESSED;
+            }
+            if tick.reload_pressed {
+                flags |= RELOAD_PRESSED;
+            }
+            if tick.dash_pressed {
+                flags |= DASH_PRESSED;
+            }
+            flags |= (encode_selection(tick.weapon_selection) as u16) << SELECTION_SHIFT;
+            bytes.extend_from_slice(&flags.to_le_bytes());
+            for vector in [tick.player_movement, tick.aim_target, tick.aim_direction]
+                .into_iter()
+                .flatten()
+            {
+                bytes.extend_from_slice(&vector.x.to_le_bytes());
+                bytes.extend_from_slice(&vector.y.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut reader = ByteReader { bytes };
+        if reader.take(4)? != MAGIC {
+            bail!("not a replay file");
+        }
+        let version = reader.u8()?;
+        if version != FORMAT_VERSION {
+            bail!("unsupported replay version {}", version);
+        }
+        let mut replay = Replay::new(reader.u64()?);
+        let level_length = reader.u16()?;
+        if level_length > 0 {
+            let level = reader.take(level_length as usize)?;
+            replay.level = Some(String::from_utf8(level.to_vec()).context("invalid level path")?);
+        }
+        let runs = reader.u32()?;
+        for _ in 0..runs {
+            let count = reader.u16()?;
+            let flags = reader.u16()?;
+            let mut vector = |flag: u16| -> anyhow::Result<Option<Vec2>> {
+                if flags & flag == 0 {
+                    return Ok(None);
+                }
+                Ok(Some(Vec2::new(reader.f32()?, reader.f32()?)))
+            };
+            let tick = TickActions {
+                player_movement: vector(HAS_MOVEMENT)?,
+                aim_target: vector(HAS_AIM_TARGET)?,
+                aim_direction: vector(HAS_AIM_DIRECTION)?,
+                trigger_pressed: flags & TRIGGER_PRESSED != 0,
+                reload_pressed: flags & RELOAD_PRESSED != 0,
+                dash_pressed: flags & DASH_PRESSED != 0,
+                weapon_selection: decode_selection(
+                    ((flags >> SELECTION_SHIFT) & SELECTION_MASK) as u8,
+                ),
+            };
+            replay
+                .ticks
+                .extend(std::iter::repeat(tick).take(count as usize));
+        }
+        Ok(replay)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, self.to_bytes()).with_context(|| format!("writing {}", path.display()))
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let bytes = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        Replay::from_bytes(&bytes)
+    }
+}
+
+/// Packs a weapon selection into 3 bits of the flags
+fn encode_selection(selection: Option<WeaponSelection>) -> u8 {
+    match selection {
+        None => 0,
+        Some(WeaponSelection::Next) => 1,
+        Some(WeaponSelection::Previous) => 2,
+        Some(WeaponSelection::Slot(slot)) => 3 + slot.min(4) as u8,
+    }
+}
+
+fn decode_selection(code: u8) -> Option<WeaponSelection> {
+    match code {
+        0 => None,
+        1 => Some(WeaponSelection::Next),
+        2 => Some(WeaponSelection::Previous),
+        slot => Some(WeaponSelection::Slot((slot - 3) as usize)),
+    }
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, count: usize) -> anyhow::Result<&'a [u8]> {
+        if self.bytes.len() < count {
+            bail!("replay file is truncated");
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn f32(&mut self) -> anyhow::Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+}
+
+/// The current run as it is being played, only recorded when it is saved with `--record`
+#[derive(Default, Debug)]
+pub struct Recording(pub Replay);
+
+/// A replay being fed into [Actions]
+#[derive(Debug)]
+pub struct Playback {
+    replay: Replay,
+    next_tick: usize,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Self {
+        Playback {
+            replay,
+            next_tick: 0,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next_tick >= self.replay.ticks.len()
+    }
+}
+
+fn load_playback(mut commands: Commands, settings: Res<ReplaySettings>) {
+    if let Some(path) = &settings.play_from {
+        match Replay::load(path) {
+            Ok(replay) => commands.insert_resource(Playback::new(replay)),
+            Err(error) => error!("Failed to load replay {}: {:?}", path.display(), error),
+        }
+    }
+}
+
+fn start_playback(
+    playback: Option<ResMut<Playback>>,
+    mut seed_mode: ResMut<SeedMode>,
+    mut source: ResMut<ActionSource>,
+    mut choice: ResMut<ArenaChoice>,
+    asset_server: Res<AssetServer>,
+) {
+    if let Some(mut playback) = playback {
+        playback.next_tick = 0;
+        *seed_mode = SeedMode::Fixed(playback.replay.seed);
+        *source = ActionSource::Replay;
+        // the levels are loaded already, so this only looks up the handle
+        *choice = match &playback.replay.level {
+            Some(path) => ArenaChoice::Level(asset_server.load(path.as_str())),
+            None => ArenaChoice::Generated,
+        };
+    }
+}
+
+fn start_recording(
+    settings: Res<ReplaySettings>,
+    mut recording: ResMut<Recording>,
+    rng: Res<GameRng>,
+    choice: Res<ArenaChoice>,
+    asset_server: Res<AssetServer>,
+) {
+    if settings.record_to.is_some() {
+        recording.0 = Replay::new(rng.seed());
+        if let ArenaChoice::Level(handle) = &*choice {
+            recording.0.level = asset_server
+                .get_handle_path(handle)
+                .map(|path| path.path().to_string_lossy().into_owned());
+        }
+    }
+}
+
+fn play_tick(
+    playback: Option<ResMut<Playback>>,
+    mut actions: ResMut<Actions>,
+    mut source: ResMut<ActionSource>,
+) {
+    let mut playback = match playback {
+        Some(playback) if *source == ActionSource::Replay => playback,
+        _ => return,
+    };
+    match playback.replay.ticks.get(playback.next_tick) {
+        Some(tick) => {
+            tick.apply(&mut actions);
+            playback.next_tick += 1;
+        }
+        None => {
+            info!(
+                "Replay finished after {} ticks, handing control back to the player",
+                playback.next_tick
+            );
+            *source = ActionSource::Input;
+        }
+    }
+}
+
+fn record_tick(
+    settings: Res<ReplaySettings>,
+    mut recording: ResMut<Recording>,
+    actions: Res<Actions>,
+) {
+    if settings.record_to.is_some() {
+        recording.0.ticks.push(TickActions::from_actions(&actions));
+    }
+}
+
+fn finish_run(
+    settings: Res<ReplaySettings>,
+    recording: Res<Recording>,
+    mut source: ResMut<ActionSource>,
+) {
+    if *source == ActionSource::Replay {
+        *source = ActionSource::Input;
+    }
+    if let Some(path) = &settings.record_to {
+        match recording.0.save(path) {
+            Ok(()) => info!(
+                "Recorded {} ticks with seed {} to {}",
+                recording.0.ticks.len(),
+                recording.0.seed,
+                path.display()
+            ),
+            Err(error) => error!("Failed to save replay: {:?}", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tick(flags: u16) -> TickActions {
+        TickActions {
+            player_movement: (flags & HAS_MOVEMENT != 0).then(|| Vec2::new(0.5, -1.)),
+            aim_target: (flags & HAS_AIM_TARGET != 0).then(|| Vec2::new(120.25, -64.)),
+            aim_direction: (flags & HAS_AIM_DIRECTION != 0).then(|| Vec2::new(0.6, 0.8)),
+            trigger_pressed: flags & TRIGGER_PRESSED != 0,
+            reload_pressed: flags & RELOAD_PRESSED != 0,
+            dash_pressed: flags & DASH_PRESSED != 0,
+            weapon_selection: None,
+        }
+    }
+
+    #[test]
+    fn round_trips_every_flag() {
+        let mut replay = Replay::new(0xDEAD_BEEF_1234_5678);
+        replay.level = Some("levels/pillars.level.ron".to_string());
+        for flag in [
+            0,
+            HAS_MOVEMENT,
+            HAS_AIM_TARGET,
+            HAS_AIM_DIRECTION,
+            TRIGGER_PRESSED,
+            RELOAD_PRESSED,
+            DASH_PRESSED,
+            HAS_MOVEMENT | HAS_AIM_TARGET | HAS_AIM_DIRECTION | TRIGGER_PRESSED | RELOAD_PRESSED,
+        ] {
+            replay.ticks.push(tick(flag));
+        }
+        for selection in [
+            WeaponSelection::Next,
+            WeaponSelection::Previous,
+            WeaponSelection::Slot(0),
+            WeaponSelection::Slot(4),
+        ] {
+            replay.ticks.push(TickActions {
+                weapon_selection: Some(selection),
+                ..tick(DASH_PRESSED)
+            });
+        }
+
+        assert_eq!(Replay::from_bytes(&replay.to_bytes()).unwrap(), replay);
+    }
+
+    #[test]
+    fn stores_repeated_ticks_once() {
+        let mut replay = Replay::new(7);
+        replay
+            .ticks
+            .extend(std::iter::repeat(tick(HAS_MOVEMENT)).take(1000));
+        replay.ticks.push(tick(TRIGGER_PRESSED));
+        // longer than a run can count
+        replay
+            .ticks
+            .extend(std::iter::repeat(tick(0)).take(u16::MAX as usize + 10));
+
+        let bytes = replay.to_bytes();
+        // header without a level, then four runs: two bytes count, two bytes flags and the movement vector in the first
+        assert_eq!(bytes.len(), 19 + 4 * 4 + 8);
+        assert_eq!(Replay::from_bytes(&bytes).unwrap(), replay);
+    }
+
+    #[test]
+    fn decodes_every_selection() {
+        let mut selections = vec![
+            None,
+            Some(WeaponSelection::Next),
+            Some(WeaponSelection::Previous),
+        ];
+        selections.extend((0..5).map(|slot| Some(WeaponSelection::Slot(slot))));
+        for selection in selections {
+            let code = encode_selection(selection);
+            assert!(code as u16 <= SELECTION_MASK);
+            assert_eq!(decode_selection(code), selection);
+        }
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut bytes = Replay::new(1).to_bytes();
+        bytes[4] = 1;
+        assert!(Replay::from_bytes(&bytes).is_err());
+        assert!(Replay::from_bytes(b"GOH").is_err());
+    }
+}
@@ -0,0 +1,135 @@
+use crate::GameState;
+use anyhow::bail;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct RngPlugin;
+
+/// This plugin provides the [GameRng] all gameplay randomness is drawn from
+/// Every run is reseeded according to [SeedMode], so a run can be replayed by starting it with the same seed
+/// The game inserts the [SeedMode] from the command line (`--seed <number>` or `--daily`), it is random otherwise
+impl Plugin for RngPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SeedMode>()
+            .init_resource::<GameRng>()
+            .add_system_set(
+                SystemSet::on_enter(GameState::Playing)
+                    .label(RngSystem)
+                    .with_system(reseed_run),
+            );
+    }
+}
+
+/// Label of the system reseeding the [GameRng] for a new run
+/// Systems drawing random numbers when a run starts must run after it
+#[derive(Clone, Debug, PartialEq, Eq, Hash, SystemLabel)]
+pub struct RngSystem;
+
+/// Where the seed of a run comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeedMode {
+    /// A new random seed for every run
+    Random,
+    /// The same seed for every run
+    Fixed(u64),
+    /// One seed per calendar day (UTC), so everyone plays the same runs that day
+    Daily,
+}
+
+impl Default for SeedMode {
+    fn default() -> Self {
+        SeedMode::Random
+    }
+}
+
+impl SeedMode {
+    /// Reads `--seed <number>` or `--daily` from the command line arguments
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut args = args.into_iter();
+        let mut mode = SeedMode::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seed" => match args.next().map(|seed| seed.parse()) {
+                    Some(Ok(seed)) => mode = SeedMode::Fixed(seed),
+                    _ => bail!("--seed expects a number"),
+                },
+                "--daily" => mode = SeedMode::Daily,
+                _ => {}
+            }
+        }
+        Ok(mode)
+    }
+
+    pub fn seed(&self) -> u64 {
+        match *self {
+            SeedMode::Random => rand::thread_rng().gen(),
+            SeedMode::Fixed(seed) => seed,
+            SeedMode::Daily => {
+                let days = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|since_epoch| since_epoch.as_secs() / (24 * 60 * 60))
+                    .unwrap_or_default();
+                // spread consecutive days over the seed space
+                days.wrapping_mul(0x2545_F491_4F6C_DD1D)
+            }
+        }
+    }
+}
+
+/// The subsystems drawing random numbers, each gets its own stream
+/// so e.g. firing a few more shots doesn't change where the next zombie spawns
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RngStream {
+    World,
+    Waves,
+    Weapons,
+    Drops,
+    /// Effects that don't influence the game, like camera shake
+    Cosmetic,
+}
+
+/// Seeded random number generators for the current run
+pub struct GameRng {
+    seed: u64,
+    streams: HashMap<RngStream, StdRng>,
+}
+
+impl Default for GameRng {
+    fn default() -> Self {
+        GameRng::new(SeedMode::default().seed())
+    }
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        GameRng {
+            seed,
+            streams: HashMap::default(),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Restarts all streams from a new seed
+    pub fn reseed(&mut self, seed: u64) {
+        *self = GameRng::new(seed);
+    }
+
+    pub fn stream(&mut self, stream: RngStream) -> &mut StdRng {
+        let seed = self.seed;
+        self.streams.entry(stream).or_insert_with(|| {
+            let offset = (stream as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+            StdRng::seed_from_u64(seed ^ offset)
+        })
+    }
+}
+
+fn reseed_run(mut rng: ResMut<GameRng>, seed_mode: Res<SeedMode>) {
+    rng.reseed(seed_mode.seed());
+    info!("Starting run with seed {}", rng.seed());
+}
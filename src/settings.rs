@@ -0,0 +1,609 @@
+use crate::bindings::{ControlInput, GameControl, InputBinding, KeyBindings};
+use crate::loading::{BindingAssets, FontAssets};
+use crate::ui::{
+    spawn_button, spawn_small_button, ButtonColors, ButtonPressedEvent, Focused, UiSystem, BACK,
+};
+use crate::{log_state_error, GameState};
+use anyhow::Context;
+use bevy::prelude::*;
+use bevy::window::{PresentMode, WindowMode};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+pub struct SettingsPlugin;
+
+/// This plugin applies the player's [Settings] while the game runs and saves them whenever they change
+/// The settings screen is pushed as `GameState::Settings` on top of the menu or the pause menu,
+/// going back pops it again and shows the screen it was opened from
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        // main inserts the settings loaded from disk, this only fills in the defaults for other apps
+        app.init_resource::<Settings>()
+            .init_resource::<SettingsPage>()
+            .init_resource::<AwaitingBinding>()
+            .add_system(apply_settings)
+            .add_system(save_settings)
+            .add_system_set(SystemSet::on_enter(GameState::Settings).with_system(open_settings))
+            .add_system_set(
+                SystemSet::on_update(GameState::Settings)
+                    // swallows the captured input before the buttons can react to it
+                    .with_system(capture_binding.before(UiSystem))
+                    .with_system(press_settings_button.after(UiSystem))
+                    .with_system(adjust_focused_setting.after(UiSystem))
+                    .with_system(go_back.after(press_settings_button))
+                    .with_system(show_settings_page.after(go_back))
+                    .with_system(show_setting_values.after(press_settings_button)),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::Settings).with_system(cleanup_settings));
+    }
+}
+
+/// Everything the player can configure, stored as `settings.ron` in the platform's config directory
+/// Missing fields fall back to their defaults, so older settings files keep working
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub master_volume: f32,
+    pub sfx_volume: f32,
+    /// Window size in logical pixels while not in fullscreen
+    pub resolution: (f32, f32),
+    pub fullscreen: bool,
+    pub vsync: bool,
+    /// Samples per pixel, wgpu only supports 1 (off) and 4
+    pub msaa: u32,
+    /// Replaces the default bindings from `assets/controls/default.bindings.ron` once the player changed one
+    pub key_bindings: Option<KeyBindings>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            master_volume: 1.,
+            sfx_volume: 1.,
+            resolution: (1200., 800.),
+            fullscreen: false,
+            vsync: true,
+            msaa: 4,
+            key_bindings: None,
+        }
+    }
+}
+
+/// Window sizes offered in the settings
+const RESOLUTIONS: [(f32, f32); 5] = [
+    (1200., 800.),
+    (1280., 720.),
+    (1600., 900.),
+    (1920., 1080.),
+    (2560., 1440.),
+];
+
+impl Settings {
+    /// Where the settings are stored
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("getting_over_him").join("settings.ron"))
+    }
+
+    /// There is no settings file in the browser, the settings only last until the page is closed
+    #[cfg(target_arch = "wasm32")]
+    pub fn path() -> Option<PathBuf> {
+        None
+    }
+
+    /// Reads the settings file, without one the defaults are used
+    /// A file that can't be parsed is renamed, so saving the defaults doesn't overwrite it
+    pub fn load() -> anyhow::Result<Settings> {
+        let path = match Settings::path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Settings::default()),
+        };
+        let content = std::fs::read_to_string(&path)?;
+        ron::from_str(&content).or_else(|error| {
+            let aside = path.with_extension("ron.invalid");
+            std::fs::rename(&path, &aside)
+                .with_context(|| format!("moving the unparsable {} aside", path.display()))?;
+            Err(anyhow::Error::new(error).context(format!(
+                "parsing {}, moved it to {}",
+                path.display(),
+                aside.display()
+            )))
+        })
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let path = match Settings::path() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let content = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new())?;
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// The window as configured, it still needs a title
+    pub fn window_descriptor(&self) -> WindowDescriptor {
+        WindowDescriptor {
+            width: self.resolution.0,
+            height: self.resolution.1,
+            present_mode: self.present_mode(),
+            mode: self.window_mode(),
+            ..Default::default()
+        }
+    }
+
+    pub fn msaa(&self) -> Msaa {
+        Msaa {
+            samples: if self.msaa > 1 { 4 } else { 1 },
+        }
+    }
+
+    /// Volume of sound effects after applying the master volume
+    pub fn sfx_gain(&self) -> f32 {
+        self.master_volume * self.sfx_volume
+    }
+
+    fn present_mode(&self) -> PresentMode {
+        if self.vsync {
+            PresentMode::Fifo
+        } else {
+            PresentMode::Immediate
+        }
+    }
+
+    fn window_mode(&self) -> WindowMode {
+        if self.fullscreen {
+            WindowMode::BorderlessFullscreen
+        } else {
+            WindowMode::Windowed
+        }
+    }
+}
+
+/// Which part of the settings screen is shown
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SettingsPage {
+    General,
+    Controls,
+}
+
+impl Default for SettingsPage {
+    fn default() -> Self {
+        SettingsPage::General
+    }
+}
+
+/// The control waiting for the player to press the input it should be bound to
+#[derive(Default)]
+struct AwaitingBinding(Option<GameControl>);
+
+#[derive(Component)]
+struct SettingsUi;
+
+#[derive(Component, Clone, Copy, PartialEq)]
+enum SettingsButton {
+    MasterVolume,
+    SfxVolume,
+    Resolution,
+    Fullscreen,
+    Vsync,
+    Msaa,
+    Controls,
+    Bind(GameControl),
+    ResetControls,
+    Back,
+}
+
+impl SettingsButton {
+    fn label(
+        &self,
+        settings: &Settings,
+        bindings: &KeyBindings,
+        awaiting: &AwaitingBinding,
+    ) -> String {
+        let on_off = |on: bool| if on { "On" } else { "Off" };
+        match self {
+            SettingsButton::MasterVolume => volume_label("Master volume", settings.master_volume),
+            SettingsButton::SfxVolume => volume_label("Effects volume", settings.sfx_volume),
+            SettingsButton::Resolution => format!(
+                "Resolution: {}x{}",
+                settings.resolution.0, settings.resolution.1
+            ),
+            SettingsButton::Fullscreen => format!("Fullscreen: {}", on_off(settings.fullscreen)),
+            SettingsButton::Vsync => format!("VSync: {}", on_off(settings.vsync)),
+            SettingsButton::Msaa => format!("Anti-aliasing: {}", on_off(settings.msaa > 1)),
+            SettingsButton::Controls => "Controls".to_string(),
+            SettingsButton::Bind(control) if awaiting.0 == Some(*control) => {
+                format!("{}: press an input", control.label())
+            }
+            SettingsButton::Bind(control) => {
+                let inputs: Vec<String> = bindings
+                    .get(*control)
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect();
+                format!("{}: {}", control.label(), inputs.join(", "))
+            }
+            SettingsButton::ResetControls => "Reset controls".to_string(),
+            SettingsButton::Back => "Back".to_string(),
+        }
+    }
+
+    /// Changes the setting behind the button by `step`, volumes only wrap around when `wrap` is set
+    fn change(&self, settings: &mut Settings, step: i32, wrap: bool) {
+        match self {
+            SettingsButton::MasterVolume => {
+                settings.master_volume = step_volume(settings.master_volume, step, wrap)
+            }
+            SettingsButton::SfxVolume => {
+                settings.sfx_volume = step_volume(settings.sfx_volume, step, wrap)
+            }
+            SettingsButton::Resolution => {
+                // an edited resolution that isn't offered continues with the first one
+                let current = RESOLUTIONS
+                    .iter()
+                    .position(|resolution| *resolution == settings.resolution)
+                    .map_or(-1, |index| index as i32);
+                let next = (current + step).rem_euclid(RESOLUTIONS.len() as i32);
+                settings.resolution = RESOLUTIONS[next as usize];
+            }
+            SettingsButton::Fullscreen => settings.fullscreen = !settings.fullscreen,
+            SettingsButton::Vsync => settings.vsync = !settings.vsync,
+            SettingsButton::Msaa => settings.msaa = if settings.msaa > 1 { 1 } else { 4 },
+            _ => {}
+        }
+    }
+}
+
+fn volume_label(name: &str, volume: f32) -> String {
+    format!("{}: {:.0}%", name, volume * 100.)
+}
+
+/// Moves a volume in steps of 10%
+fn step_volume(volume: f32, step: i32, wrap: bool) -> f32 {
+    let steps = (volume * 10.).round() as i32 + step;
+    let steps = if wrap {
+        steps.rem_euclid(11)
+    } else {
+        steps.clamp(0, 10)
+    };
+    steps as f32 / 10.
+}
+
+fn apply_settings(
+    settings: Res<Settings>,
+    mut windows: ResMut<Windows>,
+    mut msaa: ResMut<Msaa>,
+    mut bindings: ResMut<KeyBindings>,
+) {
+    // the window and msaa were created from the loaded settings
+    if !settings.is_changed() || settings.is_added() {
+        return;
+    }
+    if let Some(window) = windows.get_primary_mut() {
+        if window.mode() != settings.window_mode() {
+            window.set_mode(settings.window_mode());
+        }
+        let (width, height) = settings.resolution;
+        if (window.requested_width(), window.requested_height()) != (width, height) {
+            window.set_resolution(width, height);
+        }
+        if window.present_mode() != settings.present_mode() {
+            window.set_present_mode(settings.present_mode());
+        }
+    }
+    if msaa.samples != settings.msaa().samples {
+        *msaa = settings.msaa();
+    }
+    if let Some(key_bindings) = &settings.key_bindings {
+        *bindings = key_bindings.clone();
+    }
+}
+
+fn save_settings(settings: Res<Settings>) {
+    if !settings.is_changed() || settings.is_added() {
+        return;
+    }
+    if let Err(error) = settings.save() {
+        warn!("Failed to save the settings: {:?}", error);
+    }
+}
+
+fn open_settings(mut page: ResMut<SettingsPage>, mut awaiting: ResMut<AwaitingBinding>) {
+    *page = SettingsPage::General;
+    awaiting.0 = None;
+}
+
+/// Binds the next pressed key, mouse button or gamepad button to the awaited control
+/// Keyboard and mouse inputs replace the keyboard and mouse bindings, gamepad buttons the gamepad bindings
+fn capture_binding(
+    mut awaiting: ResMut<AwaitingBinding>,
+    mut settings: ResMut<Settings>,
+    bindings: Res<KeyBindings>,
+    mut keyboard: ResMut<Input<KeyCode>>,
+    mut mouse: ResMut<Input<MouseButton>>,
+    mut gamepad_buttons: ResMut<Input<GamepadButton>>,
+) {
+    let control = match awaiting.0 {
+        Some(control) => control,
+        None => return,
+    };
+    if keyboard.clear_just_pressed(KeyCode::Escape) {
+        awaiting.0 = None;
+        return;
+    }
+    let key = keyboard.get_just_pressed().next().copied();
+    let mouse_button = mouse.get_just_pressed().next().copied();
+    let gamepad_button = gamepad_buttons.get_just_pressed().next().copied();
+    let binding = if let Some(key) = key {
+        keyboard.clear_just_pressed(key);
+        InputBinding::Key(key)
+    } else if let Some(button) = mouse_button {
+        mouse.clear_just_pressed(button);
+        InputBinding::Mouse(button)
+    } else if let Some(button) = gamepad_button {
+        gamepad_buttons.clear_just_pressed(button);
+        InputBinding::Gamepad(button.1)
+    } else {
+        return;
+    };
+
+    let is_gamepad = |binding: &InputBinding| matches!(binding, InputBinding::Gamepad(_));
+    let mut inputs: Vec<InputBinding> = bindings
+        .get(control)
+        .iter()
+        .filter(|bound| is_gamepad(bound) != is_gamepad(&binding))
+        .copied()
+        .collect();
+    // keyboard and mouse inputs are listed before gamepad buttons
+    if is_gamepad(&binding) {
+        inputs.push(binding);
+    } else {
+        inputs.insert(0, binding);
+    }
+    let mut key_bindings = settings
+        .key_bindings
+        .clone()
+        .unwrap_or_else(|| bindings.clone());
+    key_bindings.rebind(control, inputs);
+    settings.key_bindings = Some(key_bindings);
+    awaiting.0 = None;
+}
+
+#[allow(clippy::too_many_arguments)]
+fn press_settings_button(
+    mut pressed_events: EventReader<ButtonPressedEvent>,
+    button_query: Query<&SettingsButton>,
+    mut settings: ResMut<Settings>,
+    mut page: ResMut<SettingsPage>,
+    mut awaiting: ResMut<AwaitingBinding>,
+    mut bindings: ResMut<KeyBindings>,
+    binding_assets: Res<BindingAssets>,
+    loaded_bindings: Res<Assets<KeyBindings>>,
+    mut state: ResMut<State<GameState>>,
+) {
+    for ButtonPressedEvent(entity) in pressed_events.iter() {
+        let button = match button_query.get(*entity) {
+            Ok(button) => *button,
+            Err(_) => continue,
+        };
+        match button {
+            SettingsButton::Controls => *page = SettingsPage::Controls,
+            SettingsButton::Bind(control) => awaiting.0 = Some(control),
+            SettingsButton::ResetControls => {
+                settings.key_bindings = None;
+                if let Some(defaults) = loaded_bindings.get(&binding_assets.key_bindings) {
+                    *bindings = defaults.clone();
+                }
+            }
+            SettingsButton::Back => match *page {
+                SettingsPage::General => log_state_error(state.pop()),
+                SettingsPage::Controls => *page = SettingsPage::General,
+            },
+            _ => button.change(&mut settings, 1, true),
+        }
+    }
+}
+
+/// Left and right change the focused setting without cycling through it
+fn adjust_focused_setting(
+    bindings: Res<KeyBindings>,
+    input: ControlInput,
+    focused_query: Query<&SettingsButton, With<Focused>>,
+    mut settings: ResMut<Settings>,
+) {
+    let step = if bindings.just_pressed(GameControl::Left, &input) {
+        -1
+    } else if bindings.just_pressed(GameControl::Right, &input) {
+        1
+    } else {
+        return;
+    };
+    for button in focused_query.iter() {
+        button.change(&mut settings, step, false);
+    }
+}
+
+fn go_back(
+    input: ControlInput,
+    awaiting: Res<AwaitingBinding>,
+    mut page: ResMut<SettingsPage>,
+    mut state: ResMut<State<GameState>>,
+) {
+    if awaiting.0.is_some() || !BACK.iter().any(|binding| input.just_pressed(binding)) {
+        return;
+    }
+    match *page {
+        SettingsPage::General => log_state_error(state.pop()),
+        SettingsPage::Controls => *page = SettingsPage::General,
+    }
+}
+
+/// Rebuilds the settings screen whenever another page is opened
+#[allow(clippy::too_many_arguments)]
+fn show_settings_page(
+    mut commands: Commands,
+    page: Res<SettingsPage>,
+    font_assets: Res<FontAssets>,
+    button_colors: Res<ButtonColors>,
+    settings: Res<Settings>,
+    bindings: Res<KeyBindings>,
+    awaiting: Res<AwaitingBinding>,
+    ui_query: Query<Entity, With<SettingsUi>>,
+) {
+    if !page.is_changed() {
+        return;
+    }
+    for entity in ui_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    let text = |value: &str, font_size: f32| TextBundle {
+        style: Style {
+            margin: Rect::all(Val::Px(4.0)),
+            ..Default::default()
+        },
+        text: Text::with_section(
+            value,
+            TextStyle {
+                font: font_assets.fira_sans.clone(),
+                font_size,
+                color: button_colors.text,
+            },
+            Default::default(),
+        ),
+        ..Default::default()
+    };
+    let row = || NodeBundle {
+        style: Style {
+            flex_direction: FlexDirection::Row,
+            ..Default::default()
+        },
+        color: Color::NONE.into(),
+        ..Default::default()
+    };
+    let label = |button: SettingsButton| button.label(&settings, &bindings, &awaiting);
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: Color::rgba(0., 0., 0., 0.8).into(),
+            ..Default::default()
+        })
+        .insert(SettingsUi)
+        .with_children(|parent| {
+            match *page {
+                SettingsPage::General => {
+                    parent.spawn_bundle(text("Settings", 60.0));
+                    parent.spawn_bundle(text("Audio", 32.0));
+                    for button in [SettingsButton::MasterVolume, SettingsButton::SfxVolume] {
+                        spawn_small_button(
+                            parent,
+                            &font_assets,
+                            &button_colors,
+                            label(button),
+                            360.0,
+                        )
+                        .insert(button);
+                    }
+                    parent.spawn_bundle(text("Video", 32.0));
+                    for button in [
+                        SettingsButton::Resolution,
+                        SettingsButton::Fullscreen,
+                        SettingsButton::Vsync,
+                        SettingsButton::Msaa,
+                    ] {
+                        spawn_small_button(
+                            parent,
+                            &font_assets,
+                            &button_colors,
+                            label(button),
+                            360.0,
+                        )
+                        .insert(button);
+                    }
+                    spawn_button(
+                        parent,
+                        &font_assets,
+                        &button_colors,
+                        label(SettingsButton::Controls),
+                        360.0,
+                    )
+                    .insert(SettingsButton::Controls);
+                }
+                SettingsPage::Controls => {
+                    parent.spawn_bundle(text("Controls", 60.0));
+                    // two controls per row, so all of them fit on small windows
+                    for controls in GameControl::ALL.chunks(2) {
+                        parent.spawn_bundle(row()).with_children(|parent| {
+                            for control in controls {
+                                let button = SettingsButton::Bind(*control);
+                                spawn_small_button(
+                                    parent,
+                                    &font_assets,
+                                    &button_colors,
+                                    label(button),
+                                    440.0,
+                                )
+                                .insert(button);
+                            }
+                        });
+                    }
+                    spawn_button(
+                        parent,
+                        &font_assets,
+                        &button_colors,
+                        label(SettingsButton::ResetControls),
+                        360.0,
+                    )
+                    .insert(SettingsButton::ResetControls);
+                }
+            }
+            spawn_button(
+                parent,
+                &font_assets,
+                &button_colors,
+                label(SettingsButton::Back),
+                360.0,
+            )
+            .insert(SettingsButton::Back);
+        });
+}
+
+/// Keeps the labels in sync with the values behind the buttons
+fn show_setting_values(
+    settings: Res<Settings>,
+    bindings: Res<KeyBindings>,
+    awaiting: Res<AwaitingBinding>,
+    button_query: Query<(&SettingsButton, &Children)>,
+    mut text_query: Query<&mut Text>,
+) {
+    if !settings.is_changed() && !bindings.is_changed() && !awaiting.is_changed() {
+        return;
+    }
+    for (button, children) in button_query.iter() {
+        let value = button.label(&settings, &bindings, &awaiting);
+        for child in children.iter() {
+            if let Ok(mut text) = text_query.get_mut(*child) {
+                if text.sections[0].value != value {
+                    text.sections[0].value = value.clone();
+                }
+            }
+        }
+    }
+}
+
+fn cleanup_settings(mut commands: Commands, ui_query: Query<Entity, With<SettingsUi>>) {
+    for entity in ui_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
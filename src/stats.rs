@@ -0,0 +1,103 @@
+use crate::combat::{CombatSystem, DamageTakenEvent, Health, KillEvent};
+use crate::enemy::Zombie;
+use crate::player::Player;
+use crate::timestep::{FixedTime, FixedUpdateAppExt};
+use crate::weapon::Bullet;
+use crate::GameState;
+use bevy::prelude::*;
+
+pub struct StatsPlugin;
+
+/// This plugin keeps track of what happened during the current run
+/// The stats are reset whenever a new run starts
+impl Plugin for StatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RunStats>()
+            .add_system_set(SystemSet::on_enter(GameState::Playing).with_system(reset_stats))
+            .add_fixed_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .after(CombatSystem)
+                    .with_system(count_shots)
+                    .with_system(count_kills)
+                    .with_system(count_damage_taken.after(count_kills))
+                    .with_system(count_time.after(count_damage_taken)),
+            );
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct RunStats {
+    pub time_survived: f32,
+    pub kills: u32,
+    pub shots_fired: u32,
+    pub damage_taken: u32,
+    /// Every kill scores the zombie's max health, multiplied by the kill streak
+    pub score: u32,
+    /// Kills in a row, each within [STREAK_WINDOW] seconds of the last one and without getting hurt
+    pub kill_streak: u32,
+    pub best_streak: u32,
+    /// Seconds left to continue the kill streak
+    pub streak_time_left: f32,
+}
+
+/// Seconds after a kill in which the next one continues the streak
+pub const STREAK_WINDOW: f32 = 3.;
+
+impl RunStats {
+    /// Score multiplier of the next kill, growing by one every five kills in a row
+    pub fn streak_multiplier(&self) -> u32 {
+        1 + self.kill_streak / 5
+    }
+
+    fn end_streak(&mut self) {
+        self.kill_streak = 0;
+        self.streak_time_left = 0.;
+    }
+}
+
+fn reset_stats(mut stats: ResMut<RunStats>) {
+    *stats = RunStats::default();
+}
+
+fn count_time(time: Res<FixedTime>, mut stats: ResMut<RunStats>) {
+    stats.time_survived += time.delta_seconds();
+    if stats.kill_streak > 0 {
+        stats.streak_time_left -= time.delta_seconds();
+        if stats.streak_time_left <= 0. {
+            stats.end_streak();
+        }
+    }
+}
+
+fn count_shots(mut stats: ResMut<RunStats>, bullet_query: Query<(), Added<Bullet>>) {
+    stats.shots_fired += bullet_query.iter().count() as u32;
+}
+
+fn count_kills(
+    mut stats: ResMut<RunStats>,
+    mut kill_events: EventReader<KillEvent>,
+    zombie_query: Query<&Health, With<Zombie>>,
+) {
+    for kill in kill_events.iter() {
+        if let Ok(health) = zombie_query.get(kill.target) {
+            stats.kills += 1;
+            stats.score += health.max * stats.streak_multiplier();
+            stats.kill_streak += 1;
+            stats.best_streak = stats.best_streak.max(stats.kill_streak);
+            stats.streak_time_left = STREAK_WINDOW;
+        }
+    }
+}
+
+fn count_damage_taken(
+    mut stats: ResMut<RunStats>,
+    mut damage_events: EventReader<DamageTakenEvent>,
+    player_query: Query<(), With<Player>>,
+) {
+    for damage in damage_events.iter() {
+        if player_query.contains(damage.target) {
+            stats.damage_taken += damage.amount;
+            stats.end_streak();
+        }
+    }
+}
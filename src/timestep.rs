@@ -0,0 +1,245 @@
+use crate::PIXELS_PER_METER;
+use bevy::ecs::schedule::{Stage, StageLabel};
+use bevy::prelude::*;
+use bevy::transform::TransformSystem;
+use bevy_rapier2d::prelude::*;
+use std::time::Duration;
+
+/// Seconds between two gameplay ticks, the physics simulation is stepped at the same rate
+pub const FIXED_TIMESTEP: f32 = 1. / 60.;
+
+pub struct FixedTimestepPlugin;
+
+/// This plugin runs gameplay simulation in the [FixedUpdateStage] at a fixed rate of 60 ticks per second
+/// Every tick first steps rapier once and then runs the gameplay systems, so the outcome doesn't depend on the frame rate
+/// Rapier has to be added without its default stages, see [FixedTimestepPlugin::physics]
+/// Entities moved by gameplay code can be smoothed between two ticks with [RenderInterpolation]
+impl Plugin for FixedTimestepPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FixedTime>()
+            .init_resource::<TickPacing>()
+            .add_stage_before(
+                CoreStage::PostUpdate,
+                FixedUpdateStage,
+                FixedTimestepStage::default(),
+            )
+            // despawned bodies are detected from the removals of the whole frame, which may have passed without a tick
+            .add_stage_before(
+                CoreStage::Last,
+                PhysicsStages::DetectDespawn,
+                SystemStage::parallel().with_system_set(
+                    RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsStages::DetectDespawn),
+                ),
+            )
+            .add_startup_system(configure_physics_timestep)
+            .add_system_to_stage(CoreStage::First, restore_simulated_translation)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                interpolate_translation
+                    .label(InterpolationSystem)
+                    .before(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+impl FixedTimestepPlugin {
+    /// Rapier without its own stages, they are run by the [FixedUpdateStage] instead
+    pub fn physics() -> RapierPhysicsPlugin<NoUserData> {
+        RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(PIXELS_PER_METER)
+            .with_default_system_setup(false)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, StageLabel)]
+pub struct FixedUpdateStage;
+
+/// The gameplay systems inside of the [FixedUpdateStage], after the physics step of the tick
+#[derive(Clone, Debug, PartialEq, Eq, Hash, StageLabel)]
+struct FixedGameplayStage;
+
+/// Most ticks run in a single frame, after a stall the rest is dropped and the game slows down instead
+const MAX_TICKS_PER_FRAME: u32 = 5;
+
+/// Label of the interpolation systems, systems relying on the rendered position should run after it
+#[derive(Clone, Debug, PartialEq, Eq, Hash, SystemLabel)]
+pub struct InterpolationSystem;
+
+/// How the [FixedUpdateStage] decides how many ticks to run per frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TickPacing {
+    /// As many ticks as fit into the real time that passed, so the game runs at the same speed on every machine
+    RealTime,
+    /// Exactly one tick per app update, independent of the clock
+    /// Used by the headless app, so tests can step the simulation tick by tick
+    OnePerUpdate,
+}
+
+impl Default for TickPacing {
+    fn default() -> Self {
+        TickPacing::RealTime
+    }
+}
+
+/// Time of the fixed gameplay tick, use this instead of [Time] in the [FixedUpdateStage]
+#[derive(Debug)]
+pub struct FixedTime {
+    step: Duration,
+    accumulator: Duration,
+    tick: u64,
+}
+
+impl Default for FixedTime {
+    fn default() -> Self {
+        FixedTime {
+            step: Duration::from_secs_f32(FIXED_TIMESTEP),
+            accumulator: Duration::ZERO,
+            tick: 0,
+        }
+    }
+}
+
+impl FixedTime {
+    pub fn delta(&self) -> Duration {
+        self.step
+    }
+
+    pub fn delta_seconds(&self) -> f32 {
+        self.step.as_secs_f32()
+    }
+
+    /// Number of ticks run since startup
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// How far the frame is between the last and the next tick, from 0 to 1
+    pub fn overstep_fraction(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.step.as_secs_f32()
+    }
+}
+
+/// Runs a physics step and its systems once per fixed tick that became due during the last frame
+pub struct FixedTimestepStage {
+    schedule: Schedule,
+}
+
+impl Default for FixedTimestepStage {
+    fn default() -> Self {
+        let mut schedule = Schedule::default();
+        for physics_stage in [
+            PhysicsStages::SyncBackend,
+            PhysicsStages::StepSimulation,
+            PhysicsStages::Writeback,
+        ] {
+            schedule.add_stage(
+                physics_stage.clone(),
+                SystemStage::parallel().with_system_set(
+                    RapierPhysicsPlugin::<NoUserData>::get_systems(physics_stage),
+                ),
+            );
+        }
+        schedule.add_stage(FixedGameplayStage, SystemStage::parallel());
+        FixedTimestepStage { schedule }
+    }
+}
+
+impl Stage for FixedTimestepStage {
+    fn run(&mut self, world: &mut World) {
+        // while the physics pipeline is inactive the run is paused, no ticks are due then
+        let active = world
+            .get_resource::<RapierConfiguration>()
+            .map_or(true, |config| config.physics_pipeline_active);
+        if active {
+            let delta = match *world.resource::<TickPacing>() {
+                TickPacing::RealTime => world.resource::<Time>().delta(),
+                TickPacing::OnePerUpdate => world.resource::<FixedTime>().step,
+            };
+            world.resource_mut::<FixedTime>().accumulator += delta;
+        }
+
+        for ticks in 0.. {
+            {
+                let mut fixed_time = world.resource_mut::<FixedTime>();
+                if fixed_time.accumulator < fixed_time.step {
+                    break;
+                }
+                if ticks == MAX_TICKS_PER_FRAME {
+                    fixed_time.accumulator = Duration::ZERO;
+                    break;
+                }
+                let step = fixed_time.step;
+                fixed_time.accumulator -= step;
+            }
+            remember_previous_translation(world);
+            self.schedule.run(world);
+            world.resource_mut::<FixedTime>().tick += 1;
+        }
+    }
+}
+
+pub trait FixedUpdateAppExt {
+    /// Adds a system set to the [FixedUpdateStage]
+    fn add_fixed_system_set(&mut self, system_set: SystemSet) -> &mut Self;
+}
+
+impl FixedUpdateAppExt for App {
+    fn add_fixed_system_set(&mut self, system_set: SystemSet) -> &mut Self {
+        self.stage(FixedUpdateStage, |stage: &mut FixedTimestepStage| {
+            stage
+                .schedule
+                .add_system_set_to_stage(FixedGameplayStage, system_set);
+            stage
+        })
+    }
+}
+
+/// Rapier is stepped once per tick, so every step has to cover exactly one tick
+fn configure_physics_timestep(mut rapier_config: ResMut<RapierConfiguration>) {
+    rapier_config.timestep_mode = TimestepMode::Fixed {
+        dt: FIXED_TIMESTEP,
+        substeps: 1,
+    };
+}
+
+/// Smooths the rendered translation of an entity moved in the [FixedUpdateStage] between two ticks
+/// Rigid bodies are moved by rapier inside the tick and drawn where the last tick left them
+/// During Update and the fixed ticks the [Transform] holds the simulated translation,
+/// only while rendering it is replaced by the interpolated one
+#[derive(Component, Debug)]
+pub struct RenderInterpolation {
+    previous: Vec3,
+    simulated: Vec3,
+}
+
+impl RenderInterpolation {
+    pub fn new(translation: Vec3) -> Self {
+        RenderInterpolation {
+            previous: translation,
+            simulated: translation,
+        }
+    }
+}
+
+fn remember_previous_translation(world: &mut World) {
+    let mut query = world.query::<(&Transform, &mut RenderInterpolation)>();
+    for (transform, mut interpolation) in query.iter_mut(world) {
+        interpolation.previous = transform.translation;
+    }
+}
+
+fn interpolate_translation(
+    fixed_time: Res<FixedTime>,
+    mut query: Query<(&mut Transform, &mut RenderInterpolation)>,
+) {
+    let alpha = fixed_time.overstep_fraction();
+    for (mut transform, mut interpolation) in query.iter_mut() {
+        interpolation.simulated = transform.translation;
+        transform.translation = interpolation.previous.lerp(interpolation.simulated, alpha);
+    }
+}
+
+fn restore_simulated_translation(mut query: Query<(&mut Transform, &RenderInterpolation)>) {
+    for (mut transform, interpolation) in query.iter_mut() {
+        transform.translation = interpolation.simulated;
+    }
+}
@@ -0,0 +1,294 @@
+use crate::bindings::{ControlInput, GameControl, InputBinding, KeyBindings};
+use crate::loading::FontAssets;
+use bevy::ecs::system::EntityCommands;
+use bevy::prelude::*;
+
+pub struct UiPlugin;
+
+/// This plugin drives the buttons of all menus
+/// Buttons spawned with [spawn_button] can be focused with the mouse, keyboard or gamepad,
+/// and every menu reacts to [ButtonPressedEvent]s instead of checking the `Interaction` itself
+impl Plugin for UiPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ButtonColors>()
+            .add_event::<ButtonPressedEvent>()
+            .add_system_set(
+                SystemSet::new()
+                    .label(UiSystem)
+                    .with_system(focus_hovered_button)
+                    .with_system(navigate_focus.after(focus_hovered_button))
+                    .with_system(press_buttons.after(navigate_focus))
+                    .with_system(consume_confirm.after(press_buttons))
+                    .with_system(color_buttons.after(press_buttons)),
+            );
+    }
+}
+
+/// Label of the systems focusing and pressing buttons
+#[derive(Clone, Debug, PartialEq, Eq, Hash, SystemLabel)]
+pub struct UiSystem;
+
+/// Inputs pressing the focused button
+/// Space and the south button also dash, see [consume_confirm]
+const CONFIRM: [InputBinding; 3] = [
+    InputBinding::Key(KeyCode::Return),
+    InputBinding::Key(KeyCode::Space),
+    InputBinding::Gamepad(GamepadButtonType::South),
+];
+
+/// Inputs leaving the current menu screen
+pub const BACK: [InputBinding; 3] = [
+    InputBinding::Key(KeyCode::Escape),
+    InputBinding::Key(KeyCode::Back),
+    InputBinding::Gamepad(GamepadButtonType::East),
+];
+
+pub struct ButtonColors {
+    pub normal: UiColor,
+    /// Hovered by the mouse or focused by keyboard or gamepad
+    pub hovered: UiColor,
+    pub pressed: UiColor,
+    pub disabled: UiColor,
+    pub text: Color,
+    pub disabled_text: Color,
+}
+
+impl Default for ButtonColors {
+    fn default() -> Self {
+        ButtonColors {
+            normal: Color::rgb(0.15, 0.15, 0.15).into(),
+            hovered: Color::rgb(0.25, 0.25, 0.25).into(),
+            pressed: Color::rgb(0.35, 0.75, 0.35).into(),
+            disabled: Color::rgb(0.1, 0.1, 0.1).into(),
+            text: Color::rgb(0.9, 0.9, 0.9),
+            disabled_text: Color::rgb(0.5, 0.5, 0.5),
+        }
+    }
+}
+
+/// The button that keyboard and gamepad input acts on, there is at most one
+#[derive(Component)]
+pub struct Focused;
+
+/// A button that can't be focused or pressed
+#[derive(Component)]
+pub struct Disabled;
+
+/// Sent when a button is clicked, or confirmed while focused
+pub struct ButtonPressedEvent(pub Entity);
+
+/// Spawns a button with a centered label as child of `parent`
+/// Insert a component on the returned entity to know which button was pressed
+pub fn spawn_button<'w, 's, 'a>(
+    parent: &'a mut ChildBuilder<'w, 's, '_>,
+    font_assets: &FontAssets,
+    button_colors: &ButtonColors,
+    label: impl Into<String>,
+    width: f32,
+) -> EntityCommands<'w, 's, 'a> {
+    let size = Size::new(Val::Px(width), Val::Px(50.0));
+    spawn_sized_button(parent, font_assets, button_colors, label.into(), size, 40.0)
+}
+
+/// Like [spawn_button], for screens with many buttons like the settings
+pub fn spawn_small_button<'w, 's, 'a>(
+    parent: &'a mut ChildBuilder<'w, 's, '_>,
+    font_assets: &FontAssets,
+    button_colors: &ButtonColors,
+    label: impl Into<String>,
+    width: f32,
+) -> EntityCommands<'w, 's, 'a> {
+    let size = Size::new(Val::Px(width), Val::Px(32.0));
+    spawn_sized_button(parent, font_assets, button_colors, label.into(), size, 22.0)
+}
+
+fn spawn_sized_button<'w, 's, 'a>(
+    parent: &'a mut ChildBuilder<'w, 's, '_>,
+    font_assets: &FontAssets,
+    button_colors: &ButtonColors,
+    label: String,
+    size: Size<Val>,
+    font_size: f32,
+) -> EntityCommands<'w, 's, 'a> {
+    let mut button = parent.spawn_bundle(ButtonBundle {
+        style: Style {
+            size,
+            margin: Rect::all(Val::Px(font_size / 5.0)),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..Default::default()
+        },
+        color: button_colors.normal,
+        ..Default::default()
+    });
+    button.with_children(|parent| {
+        parent.spawn_bundle(TextBundle {
+            text: Text::with_section(
+                label,
+                TextStyle {
+                    font: font_assets.fira_sans.clone(),
+                    font_size,
+                    color: button_colors.text,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        });
+    });
+    button
+}
+
+fn focus_hovered_button(
+    mut commands: Commands,
+    hovered_query: Query<(Entity, &Interaction), (Changed<Interaction>, Without<Disabled>)>,
+    focused_query: Query<Entity, With<Focused>>,
+) {
+    for (entity, interaction) in hovered_query.iter() {
+        if *interaction == Interaction::None || focused_query.contains(entity) {
+            continue;
+        }
+        for focused in focused_query.iter() {
+            commands.entity(focused).remove::<Focused>();
+        }
+        commands.entity(entity).insert(Focused);
+    }
+}
+
+/// Moves the focus through the buttons in reading order, wrapping around at the ends
+/// Without a focused button the first or last one gets the focus
+fn navigate_focus(
+    mut commands: Commands,
+    bindings: Res<KeyBindings>,
+    input: ControlInput,
+    button_query: Query<
+        (Entity, &GlobalTransform, Option<&Focused>),
+        (With<Button>, Without<Disabled>),
+    >,
+) {
+    let step: isize = if bindings.just_pressed(GameControl::Up, &input) {
+        -1
+    } else if bindings.just_pressed(GameControl::Down, &input) {
+        1
+    } else {
+        return;
+    };
+    let mut buttons: Vec<_> = button_query.iter().collect();
+    if buttons.is_empty() {
+        return;
+    }
+    // the UI camera looks up the y axis, so the topmost button has the largest y
+    buttons.sort_by(|(_, a, _), (_, b, _)| {
+        b.translation
+            .y
+            .total_cmp(&a.translation.y)
+            .then(a.translation.x.total_cmp(&b.translation.x))
+    });
+    let count = buttons.len() as isize;
+    let next = match buttons.iter().position(|(_, _, focused)| focused.is_some()) {
+        Some(current) => {
+            commands.entity(buttons[current].0).remove::<Focused>();
+            (current as isize + step).rem_euclid(count)
+        }
+        None if step > 0 => 0,
+        None => count - 1,
+    };
+    commands.entity(buttons[next as usize].0).insert(Focused);
+}
+
+/// A click counts once the mouse is released over the same button, so a press can still be cancelled
+fn press_buttons(
+    mut pressed: Local<Option<Entity>>,
+    mut pressed_events: EventWriter<ButtonPressedEvent>,
+    input: ControlInput,
+    interaction_query: Query<(Entity, &Interaction), (Changed<Interaction>, Without<Disabled>)>,
+    focused_query: Query<Entity, (With<Focused>, Without<Disabled>)>,
+) {
+    for (entity, interaction) in interaction_query.iter() {
+        match *interaction {
+            Interaction::Clicked => *pressed = Some(entity),
+            Interaction::Hovered if *pressed == Some(entity) => {
+                *pressed = None;
+                pressed_events.send(ButtonPressedEvent(entity));
+            }
+            _ => {
+                if *pressed == Some(entity) {
+                    *pressed = None;
+                }
+            }
+        }
+    }
+    if CONFIRM.iter().any(|binding| input.just_pressed(binding)) {
+        for entity in focused_query.iter() {
+            pressed_events.send(ButtonPressedEvent(entity));
+        }
+    }
+}
+
+/// Clears the confirming press once it pressed a button, so a run started or resumed with it
+/// doesn't see the same press as its first dash
+fn consume_confirm(
+    mut pressed_events: EventReader<ButtonPressedEvent>,
+    mut keyboard: ResMut<Input<KeyCode>>,
+    mut gamepad_buttons: ResMut<Input<GamepadButton>>,
+    gamepads: Res<Gamepads>,
+) {
+    if pressed_events.iter().count() == 0 {
+        return;
+    }
+    for binding in CONFIRM.iter() {
+        match *binding {
+            InputBinding::Key(key) => {
+                keyboard.clear_just_pressed(key);
+            }
+            InputBinding::Mouse(_) => {}
+            InputBinding::Gamepad(button) => {
+                for gamepad in gamepads.iter() {
+                    gamepad_buttons.clear_just_pressed(GamepadButton(*gamepad, button));
+                }
+            }
+        }
+    }
+}
+
+fn color_buttons(
+    button_colors: Res<ButtonColors>,
+    input: ControlInput,
+    mut button_query: Query<
+        (
+            &Interaction,
+            &mut UiColor,
+            &Children,
+            Option<&Focused>,
+            Option<&Disabled>,
+        ),
+        With<Button>,
+    >,
+    mut text_query: Query<&mut Text>,
+) {
+    let confirming = CONFIRM.iter().any(|binding| input.pressed(binding));
+    for (interaction, mut color, children, focused, disabled) in button_query.iter_mut() {
+        let (next, text_color) = if disabled.is_some() {
+            (button_colors.disabled, button_colors.disabled_text)
+        } else if *interaction == Interaction::Clicked || (focused.is_some() && confirming) {
+            (button_colors.pressed, button_colors.text)
+        } else if *interaction == Interaction::Hovered || focused.is_some() {
+            (button_colors.hovered, button_colors.text)
+        } else {
+            (button_colors.normal, button_colors.text)
+        };
+        if color.0 != next.0 {
+            *color = next;
+        }
+        for child in children.iter() {
+            let mut text = match text_query.get_mut(*child) {
+                Ok(text) => text,
+                Err(_) => continue,
+            };
+            for section in text.sections.iter_mut() {
+                if section.style.color != text_color {
+                    section.style.color = text_color;
+                }
+            }
+        }
+    }
+}
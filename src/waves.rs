@@ -0,0 +1,354 @@
+use crate::arena::ArenaLayout;
+use crate::camera::{MainCamera, ViewportSize};
+use crate::enemy::{spawn_enemy, EnemyRoster, Zombie};
+use crate::loading::{EnemyAssets, WaveAssets};
+use crate::player::Player;
+use crate::rng::{GameRng, RngStream};
+use crate::timestep::{FixedTime, FixedUpdateAppExt};
+use crate::GameState;
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::Deserialize;
+use std::f32::consts::TAU;
+
+pub struct WavePlugin;
+
+/// This plugin spawns zombies in waves as described by the wave table in `assets/waves`
+/// Between two waves the player gets a rest, the next wave starts once that runs out
+impl Plugin for WavePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WaveDirector>()
+            .add_system_set(SystemSet::on_enter(GameState::Playing).with_system(reset_waves))
+            .add_fixed_system_set(SystemSet::on_update(GameState::Playing).with_system(run_waves));
+    }
+}
+
+#[derive(Deserialize, TypeUuid, Clone, Debug)]
+#[uuid = "0b6f4c1e-2a7d-4f35-8e91-6c3d5a9b2e47"]
+pub struct WaveTable {
+    /// Seconds before the first wave starts
+    pub warmup: f32,
+    pub spawn_area: SpawnArea,
+    pub waves: Vec<WaveDef>,
+    /// After the last wave, it is repeated with this fraction of additional enemies per extra wave
+    pub endless_growth: f32,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum SpawnArea {
+    /// Fixed positions in the world, a random one is picked for every spawn
+    Points(Vec<Vec2>),
+    /// A ring around the camera, `margin` pixels outside of the screen corners
+    OffscreenRing { margin: f32 },
+    /// The spawn points of the arena, a random one is picked for every spawn
+    Arena,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct WaveDef {
+    pub groups: Vec<EnemyGroup>,
+    /// Seconds between two spawns
+    pub spawn_interval: f32,
+    /// Seconds of rest after the wave is cleared
+    pub rest: f32,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct EnemyGroup {
+    pub enemy: String,
+    pub count: u32,
+}
+
+impl WaveTable {
+    /// The definition of a wave, counting from 1
+    pub fn wave(&self, number: u32) -> Option<WaveDef> {
+        let last = self.waves.len() as u32;
+        if number == 0 || last == 0 {
+            return None;
+        }
+        if number <= last {
+            return Some(self.waves[number as usize - 1].clone());
+        }
+
+        let scale = 1. + self.endless_growth * (number - last) as f32;
+        let mut wave = self.waves[last as usize - 1].clone();
+        for group in wave.groups.iter_mut() {
+            group.count = (group.count as f32 * scale).round() as u32;
+        }
+        Some(wave)
+    }
+}
+
+#[derive(Debug)]
+pub enum WavePhase {
+    /// Counting down to the next wave
+    Resting(Timer),
+    /// Enemies of the current wave are still being spawned
+    Spawning,
+    /// Everything is spawned, waiting for the player to kill the rest
+    Clearing,
+}
+
+impl Default for WavePhase {
+    fn default() -> Self {
+        WavePhase::Resting(Timer::from_seconds(0., false))
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct WaveDirector {
+    wave: u32,
+    phase: WavePhase,
+    pending: Vec<String>,
+    spawn_timer: Timer,
+}
+
+impl WaveDirector {
+    /// The current wave counting from 1, 0 before the first wave started
+    pub fn wave(&self) -> u32 {
+        self.wave
+    }
+
+    pub fn phase(&self) -> &WavePhase {
+        &self.phase
+    }
+
+    /// Seconds until the next wave starts, if the player is currently resting
+    pub fn countdown(&self) -> Option<f32> {
+        match &self.phase {
+            WavePhase::Resting(timer) => {
+                Some((timer.duration().as_secs_f32() - timer.elapsed_secs()).max(0.))
+            }
+            _ => None,
+        }
+    }
+
+    /// Enemies of the current wave that haven't been spawned yet
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn start_wave(&mut self, wave: &WaveDef, rng: &mut impl Rng) {
+        self.wave += 1;
+        self.pending = wave
+            .groups
+            .iter()
+            .flat_map(|group| (0..group.count).map(move |_| group.enemy.clone()))
+            .collect();
+        self.pending.shuffle(rng);
+        self.spawn_timer = Timer::from_seconds(wave.spawn_interval.max(0.01), true);
+        self.phase = WavePhase::Spawning;
+    }
+}
+
+fn reset_waves(
+    mut director: ResMut<WaveDirector>,
+    wave_assets: Res<WaveAssets>,
+    tables: Res<Assets<WaveTable>>,
+) {
+    let warmup = tables
+        .get(&wave_assets.waves)
+        .map(|table| table.warmup)
+        .unwrap_or_default();
+    *director = WaveDirector {
+        phase: WavePhase::Resting(Timer::from_seconds(warmup, false)),
+        ..Default::default()
+    };
+}
+
+fn run_waves(
+    mut commands: Commands,
+    time: Res<FixedTime>,
+    mut director: ResMut<WaveDirector>,
+    mut game_rng: ResMut<GameRng>,
+    wave_assets: Res<WaveAssets>,
+    tables: Res<Assets<WaveTable>>,
+    enemy_assets: Res<EnemyAssets>,
+    rosters: Res<Assets<EnemyRoster>>,
+    asset_server: Res<AssetServer>,
+    layout: Res<ArenaLayout>,
+    viewport: Res<ViewportSize>,
+    player_query: Query<&Transform, With<Player>>,
+    projection_query: Query<&OrthographicProjection, With<MainCamera>>,
+    zombie_query: Query<(), With<Zombie>>,
+) {
+    let (table, roster) = match (
+        tables.get(&wave_assets.waves),
+        rosters.get(&enemy_assets.roster),
+    ) {
+        (Some(table), Some(roster)) => (table, roster),
+        _ => return,
+    };
+    let rng = game_rng.stream(RngStream::Waves);
+
+    match &mut director.phase {
+        WavePhase::Resting(timer) => {
+            if timer.tick(time.delta()).finished() {
+                if let Some(wave) = table.wave(director.wave + 1) {
+                    director.start_wave(&wave, rng);
+                }
+            }
+        }
+        WavePhase::Spawning => {
+            let spawns = director.spawn_timer.tick(time.delta()).times_finished();
+            for _ in 0..spawns {
+                let name = match director.pending.pop() {
+                    Some(name) => name,
+                    None => break,
+                };
+                let def = match roster.get(&name) {
+                    Some(def) => def,
+                    None => {
+                        warn!("Wave {} spawns unknown enemy '{}'", director.wave, name);
+                        continue;
+                    }
+                };
+                match spawn_position(
+                    &table.spawn_area,
+                    player_query
+                        .get_single()
+                        .ok()
+                        .map(|player| player.translation.truncate()),
+                    viewport.half_view_at(projection_query.get_single().ok()),
+                    &layout,
+                    def.radius,
+                    rng,
+                ) {
+                    Some(position) => {
+                        spawn_enemy(&mut commands, &asset_server, &name, def, position);
+                    }
+                    None => {
+                        // keeps the enemy, so the wave doesn't clear without it
+                        warn!("Found no position to spawn '{}', retrying", name);
+                        director.pending.push(name);
+                        break;
+                    }
+                }
+            }
+            if director.pending.is_empty() {
+                director.phase = WavePhase::Clearing;
+            }
+        }
+        WavePhase::Clearing => {
+            if zombie_query.is_empty() {
+                let rest = table
+                    .wave(director.wave)
+                    .map(|wave| wave.rest)
+                    .unwrap_or_default();
+                director.phase = WavePhase::Resting(Timer::from_seconds(rest, false));
+            }
+        }
+    }
+}
+
+/// Margin of the offscreen ring used when the spawn area has no positions to offer
+const FALLBACK_RING_MARGIN: f32 = 64.;
+
+fn spawn_position(
+    area: &SpawnArea,
+    player: Option<Vec2>,
+    half_view: Vec2,
+    layout: &ArenaLayout,
+    radius: f32,
+    rng: &mut impl Rng,
+) -> Option<Vec2> {
+    let points = match area {
+        SpawnArea::Points(points) => points,
+        SpawnArea::Arena => &layout.spawn_points,
+        SpawnArea::OffscreenRing { margin } => {
+            return offscreen_position(*margin, player, half_view, layout, radius, rng)
+        }
+    };
+    // e.g. a level without spawn points, spawning offscreen beats not spawning at all
+    match points.choose(rng) {
+        Some(point) => Some(*point),
+        None => offscreen_position(FALLBACK_RING_MARGIN, player, half_view, layout, radius, rng),
+    }
+}
+
+/// A position on a ring around the player, just outside of the view
+/// Centred on the simulated player instead of the camera, so shake and smoothing don't move the ring
+fn offscreen_position(
+    margin: f32,
+    player: Option<Vec2>,
+    half_view: Vec2,
+    layout: &ArenaLayout,
+    radius: f32,
+    rng: &mut impl Rng,
+) -> Option<Vec2> {
+    let center = player?;
+    let distance = half_view.length() + margin;
+
+    // prefer open positions inside the arena, but never skip a spawn because of it
+    let mut position = center;
+    for _ in 0..8 {
+        let angle = rng.gen_range(0.0..TAU);
+        position = center + Vec2::new(angle.cos(), angle.sin()) * distance;
+        if !layout.is_blocked(position, radius) {
+            break;
+        }
+    }
+    Some(position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> WaveTable {
+        let wave = |zombies: u32, rest: f32| WaveDef {
+            groups: vec![
+                EnemyGroup {
+                    enemy: "zombie".to_string(),
+                    count: zombies,
+                },
+                EnemyGroup {
+                    enemy: "runner".to_string(),
+                    count: 2,
+                },
+            ],
+            spawn_interval: 0.5,
+            rest,
+        };
+        WaveTable {
+            warmup: 3.,
+            spawn_area: SpawnArea::Arena,
+            waves: vec![wave(5, 10.), wave(10, 8.)],
+            endless_growth: 0.5,
+        }
+    }
+
+    fn counts(wave: &WaveDef) -> Vec<u32> {
+        wave.groups.iter().map(|group| group.count).collect()
+    }
+
+    #[test]
+    fn waves_are_looked_up_from_the_table() {
+        let table = table();
+        assert!(table.wave(0).is_none());
+        assert_eq!(counts(&table.wave(1).unwrap()), vec![5, 2]);
+        let second = table.wave(2).unwrap();
+        assert_eq!(counts(&second), vec![10, 2]);
+        assert_eq!(second.rest, 8.);
+    }
+
+    #[test]
+    fn endless_waves_grow_from_the_last_one() {
+        let table = table();
+        assert_eq!(counts(&table.wave(3).unwrap()), vec![15, 3]);
+        assert_eq!(counts(&table.wave(4).unwrap()), vec![20, 4]);
+        assert_eq!(counts(&table.wave(12).unwrap()), vec![60, 12]);
+        assert_eq!(table.wave(12).unwrap().rest, 8.);
+    }
+
+    #[test]
+    fn an_empty_table_has_no_waves() {
+        let table = WaveTable {
+            waves: vec![],
+            ..table()
+        };
+        assert!(table.wave(1).is_none());
+    }
+}
@@ -0,0 +1,590 @@
+use crate::actions::{Actions, TickActionsSystem, WeaponSelection};
+use crate::camera::CameraShakeEvent;
+use crate::combat::ExplosionEvent;
+use crate::lifetime::{Lifetime, LifetimeSystem};
+use crate::player::{Player, PLAYER_RADIUS};
+use crate::rng::{GameRng, RngStream};
+use crate::timestep::{FixedTime, FixedUpdateAppExt};
+use crate::{GameState, RunEntity};
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::{DrawMode, FillMode, GeometryBuilder};
+use bevy_prototype_lyon::shapes;
+use bevy_rapier2d::prelude::*;
+use rand::Rng;
+
+pub struct WeaponPlugin;
+
+/// This plugin switches, fires and reloads the weapons in the player's [Inventory]
+/// Shots are paced by the weapon's fire rate, so they don't depend on the frame rate
+impl Plugin for WeaponPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_update(GameState::Playing).with_system(fade_explosion_flashes),
+        )
+        // running in the fixed tick keeps the fire rate exact and lets replays reproduce every shot
+        .add_fixed_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .after(TickActionsSystem)
+                .with_system(select_weapon)
+                .with_system(reload_weapon.after(select_weapon))
+                .with_system(fire_weapon.after(reload_weapon))
+                .with_system(expire_bullets.after(LifetimeSystem)),
+        );
+    }
+}
+
+/// Physics group of all bullets, so pellets of the same shot don't collide with each other
+pub const BULLET_GROUP: u32 = 0b10;
+
+#[derive(Component, Debug)]
+pub struct Bullet {
+    pub damage: u32,
+    /// What happens when the bullet hits something, counters in it are used up with every hit
+    pub behaviour: ProjectileBehaviour,
+    /// Distance the bullet has covered in pixels
+    pub travelled: f32,
+    pub range: f32,
+}
+
+impl Bullet {
+    pub fn is_out_of_range(&self) -> bool {
+        self.travelled >= self.range
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProjectileBehaviour {
+    /// Used up by the first thing it hits
+    Single,
+    /// Flies through up to `hits` zombies and stops at walls
+    Piercing { hits: u32 },
+    /// Blows up on the first hit or at the end of its range, hurting and pushing away all zombies in the `radius`
+    Explosive {
+        radius: f32,
+        damage: u32,
+        /// Speed in pixels per second zombies at the center of the blast are pushed away with
+        knockback: f32,
+    },
+    /// Bounces off walls up to `bounces` times and is used up by the first zombie it hits
+    Bouncing { bounces: u32 },
+}
+
+/// The weapons the player can own, in the order of their inventory slots
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum WeaponKind {
+    Pistol,
+    Shotgun,
+    Smg,
+    Rifle,
+    Launcher,
+}
+
+impl WeaponKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WeaponKind::Pistol => "Pistol",
+            WeaponKind::Shotgun => "Shotgun",
+            WeaponKind::Smg => "SMG",
+            WeaponKind::Rifle => "Rifle",
+            WeaponKind::Launcher => "Launcher",
+        }
+    }
+
+    /// Looks up the weapon of an item in an enemy's drop table
+    pub fn from_item(item: &str) -> Option<Self> {
+        match item {
+            "pistol" => Some(WeaponKind::Pistol),
+            "shotgun" => Some(WeaponKind::Shotgun),
+            "smg" => Some(WeaponKind::Smg),
+            "rifle" => Some(WeaponKind::Rifle),
+            "launcher" => Some(WeaponKind::Launcher),
+            _ => None,
+        }
+    }
+
+    /// Inventory slot of the weapon, counting from 0
+    pub fn slot(&self) -> usize {
+        *self as usize
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Weapon {
+    pub kind: WeaponKind,
+    /// Shots per second while the trigger is held
+    pub fire_rate: f32,
+    /// Speed of the projectiles in pixels per second
+    pub projectile_speed: f32,
+    /// Full opening angle of the cone the pellets are spread over, in radians
+    pub spread: f32,
+    /// Projectiles fired with every shot
+    pub pellets: u32,
+    /// Damage of a single projectile
+    pub damage: u32,
+    pub projectile: ProjectileBehaviour,
+    /// Seconds a projectile flies before it is removed
+    pub projectile_lifetime: f32,
+    /// Distance in pixels a projectile flies before it is removed
+    pub range: f32,
+    pub magazine_size: u32,
+    /// Seconds it takes to refill the magazine
+    pub reload_time: f32,
+    /// Rounds left in the magazine
+    pub ammo: u32,
+    /// Rounds left to refill the magazine with, `None` for unlimited ammo
+    pub reserve: Option<u32>,
+    /// Seconds until the next shot can be fired
+    cooldown: f32,
+    reload: Option<Timer>,
+}
+
+impl Weapon {
+    pub fn new(kind: WeaponKind) -> Self {
+        let weapon = Weapon {
+            kind,
+            fire_rate: 4.,
+            projectile_speed: 500.,
+            spread: 0.05,
+            pellets: 1,
+            damage: 25,
+            projectile: ProjectileBehaviour::Single,
+            projectile_lifetime: 2.,
+            range: 600.,
+            magazine_size: 12,
+            reload_time: 1.2,
+            ammo: 0,
+            reserve: None,
+            cooldown: 0.,
+            reload: None,
+        };
+        let weapon = match kind {
+            WeaponKind::Pistol => weapon,
+            WeaponKind::Shotgun => Weapon {
+                fire_rate: 1.2,
+                projectile_speed: 450.,
+                spread: 0.5,
+                pellets: 8,
+                damage: 15,
+                range: 300.,
+                magazine_size: 6,
+                reload_time: 2.,
+                reserve: Some(24),
+                ..weapon
+            },
+            WeaponKind::Smg => Weapon {
+                fire_rate: 14.,
+                spread: 0.2,
+                damage: 12,
+                projectile: ProjectileBehaviour::Bouncing { bounces: 2 },
+                range: 800.,
+                magazine_size: 30,
+                reload_time: 1.5,
+                reserve: Some(120),
+                ..weapon
+            },
+            WeaponKind::Rifle => Weapon {
+                fire_rate: 1.,
+                projectile_speed: 900.,
+                spread: 0.,
+                damage: 80,
+                projectile: ProjectileBehaviour::Piercing { hits: 4 },
+                range: 1200.,
+                magazine_size: 5,
+                reload_time: 2.5,
+                reserve: Some(20),
+                ..weapon
+            },
+            WeaponKind::Launcher => Weapon {
+                fire_rate: 0.8,
+                projectile_speed: 300.,
+                spread: 0.02,
+                damage: 0,
+                projectile: ProjectileBehaviour::Explosive {
+                    radius: 80.,
+                    damage: 120,
+                    knockback: 500.,
+                },
+                range: 450.,
+                magazine_size: 3,
+                reload_time: 3.,
+                reserve: Some(9),
+                ..weapon
+            },
+        };
+        Weapon {
+            ammo: weapon.magazine_size,
+            ..weapon
+        }
+    }
+
+    pub fn is_reloading(&self) -> bool {
+        self.reload.is_some()
+    }
+
+    /// Progress of the current reload between 0 and 1
+    pub fn reload_progress(&self) -> Option<f32> {
+        self.reload.as_ref().map(Timer::percent)
+    }
+
+    /// Starts refilling the magazine, unless it is full, already being refilled or there is nothing left to refill it with
+    pub fn start_reload(&mut self) {
+        if self.reload.is_none() && self.ammo < self.magazine_size && self.reserve != Some(0) {
+            self.reload = Some(Timer::from_seconds(self.reload_time, false));
+        }
+    }
+
+    fn finish_reload(&mut self) {
+        let missing = self.magazine_size - self.ammo;
+        let refill = self.reserve.map_or(missing, |reserve| reserve.min(missing));
+        if let Some(reserve) = self.reserve.as_mut() {
+            *reserve -= refill;
+        }
+        self.ammo += refill;
+        self.reload = None;
+    }
+
+    /// Adds rounds to the reserve, weapons with unlimited ammo are left as they are
+    pub fn add_ammo(&mut self, rounds: u32) {
+        if let Some(reserve) = self.reserve.as_mut() {
+            *reserve += rounds;
+        }
+    }
+
+    /// Directions of the projectiles of one shot, spread randomly inside the cone around `aim`
+    pub fn muzzle_directions(&self, aim: Vec2, rng: &mut impl Rng) -> Vec<Vec2> {
+        let half_spread = self.spread / 2.;
+        (0..self.pellets)
+            .map(|_| {
+                let offset = if half_spread > 0. {
+                    rng.gen_range(-half_spread..=half_spread)
+                } else {
+                    0.
+                };
+                Mat2::from_angle(offset) * aim
+            })
+            .collect()
+    }
+}
+
+/// The weapons owned by the player and which of them is held
+#[derive(Component, Debug)]
+pub struct Inventory {
+    /// Sorted by slot
+    weapons: Vec<Weapon>,
+    selected: usize,
+}
+
+impl Inventory {
+    pub fn new(weapon: Weapon) -> Self {
+        Inventory {
+            weapons: vec![weapon],
+            selected: 0,
+        }
+    }
+
+    pub fn weapons(&self) -> &[Weapon] {
+        &self.weapons
+    }
+
+    pub fn current(&self) -> &Weapon {
+        &self.weapons[self.selected]
+    }
+
+    pub fn current_mut(&mut self) -> &mut Weapon {
+        &mut self.weapons[self.selected]
+    }
+
+    pub fn select(&mut self, selection: WeaponSelection) {
+        let count = self.weapons.len();
+        let selected = match selection {
+            WeaponSelection::Slot(slot) => {
+                match self
+                    .weapons
+                    .iter()
+                    .position(|weapon| weapon.kind.slot() == slot)
+                {
+                    Some(index) => index,
+                    None => return,
+                }
+            }
+            WeaponSelection::Next => (self.selected + 1) % count,
+            WeaponSelection::Previous => (self.selected + count - 1) % count,
+        };
+        if selected != self.selected {
+            // switching weapons interrupts the reload
+            self.current_mut().reload = None;
+            self.selected = selected;
+        }
+    }
+
+    /// Adds a new weapon and switches to it, or adds ammo if the weapon is already owned
+    pub fn collect(&mut self, kind: WeaponKind) {
+        if let Some(weapon) = self.weapons.iter_mut().find(|weapon| weapon.kind == kind) {
+            let rounds = weapon.magazine_size * 2;
+            weapon.add_ammo(rounds);
+            return;
+        }
+        let index = self
+            .weapons
+            .iter()
+            .position(|weapon| weapon.kind > kind)
+            .unwrap_or(self.weapons.len());
+        self.current_mut().reload = None;
+        self.weapons.insert(index, Weapon::new(kind));
+        self.selected = index;
+    }
+}
+
+fn select_weapon(
+    mut actions: ResMut<Actions>,
+    mut inventory_query: Query<&mut Inventory, With<Player>>,
+) {
+    // consumed by the first tick, so a frame with several ticks doesn't switch several times
+    if let Some(selection) = actions.weapon_selection.take() {
+        for mut inventory in inventory_query.iter_mut() {
+            inventory.select(selection);
+        }
+    }
+}
+
+fn reload_weapon(
+    time: Res<FixedTime>,
+    mut actions: ResMut<Actions>,
+    mut inventory_query: Query<&mut Inventory, With<Player>>,
+) {
+    let reload_pressed = std::mem::take(&mut actions.reload_pressed);
+    for mut inventory in inventory_query.iter_mut() {
+        let weapon = inventory.current_mut();
+        if reload_pressed || weapon.ammo == 0 {
+            weapon.start_reload();
+        }
+        let finished = match weapon.reload.as_mut() {
+            Some(timer) => timer.tick(time.delta()).finished(),
+            None => false,
+        };
+        if finished {
+            weapon.finish_reload();
+        }
+    }
+}
+
+fn fire_weapon(
+    mut commands: Commands,
+    time: Res<FixedTime>,
+    actions: Res<Actions>,
+    mut game_rng: ResMut<GameRng>,
+    mut player_query: Query<(&Transform, &mut Inventory), With<Player>>,
+    mut shake_events: EventWriter<CameraShakeEvent>,
+) {
+    let rng = game_rng.stream(RngStream::Weapons);
+    for (player_transform, mut inventory) in player_query.iter_mut() {
+        let weapon = inventory.current_mut();
+        weapon.cooldown -= time.delta_seconds();
+        let aim_direction = match actions.aim_direction {
+            Some(aim_direction) if actions.trigger_pressed => aim_direction,
+            _ => {
+                // don't bank shots while the trigger is released
+                weapon.cooldown = weapon.cooldown.max(0.);
+                continue;
+            }
+        };
+
+        let muzzle = player_transform.translation.truncate() + aim_direction * PLAYER_RADIUS;
+        // at low frame rates several shots can be due in one frame
+        while weapon.cooldown <= 0. && weapon.ammo > 0 && !weapon.is_reloading() {
+            weapon.cooldown += 1. / weapon.fire_rate;
+            weapon.ammo -= 1;
+            for direction in weapon.muzzle_directions(aim_direction, rng) {
+                spawn_bullet(
+                    &mut commands,
+                    muzzle,
+                    direction * weapon.projectile_speed,
+                    Bullet {
+                        damage: weapon.damage,
+                        behaviour: weapon.projectile,
+                        travelled: 0.,
+                        range: weapon.range,
+                    },
+                    weapon.projectile_lifetime,
+                );
+            }
+            shake_events.send(CameraShakeEvent { trauma: 0.015 });
+        }
+        if weapon.is_reloading() || weapon.ammo == 0 {
+            weapon.cooldown = weapon.cooldown.max(0.);
+        }
+    }
+}
+
+fn spawn_bullet(
+    commands: &mut Commands,
+    position: Vec2,
+    velocity: Vec2,
+    bullet: Bullet,
+    lifetime: f32,
+) {
+    let mut entity = commands.spawn();
+    entity
+        .insert(RigidBody::Dynamic)
+        .insert(GravityScale(0.))
+        .insert(Collider::ball(0.5))
+        .insert(CollisionGroups::new(BULLET_GROUP, u32::MAX ^ BULLET_GROUP))
+        .insert(Restitution::coefficient(0.99))
+        .insert(Sleeping::disabled())
+        .insert(ColliderMassProperties::Density(1.0))
+        .insert(ReadMassProperties::default())
+        .insert(ExternalImpulse::default())
+        .insert(Velocity {
+            linvel: velocity,
+            ..default()
+        })
+        .insert(Ccd::enabled())
+        .insert(ActiveEvents::COLLISION_EVENTS)
+        .insert(Lifetime::from_seconds(lifetime))
+        .insert(RunEntity)
+        .insert_bundle(TransformBundle::from_transform(
+            Transform::from_translation(position.extend(0.)),
+        ));
+    // piercing bullets must not be stopped by the zombies they fly through
+    if let ProjectileBehaviour::Piercing { .. } = bullet.behaviour {
+        entity.insert(Sensor(true));
+    }
+    entity.insert(bullet);
+}
+
+fn expire_bullets(
+    mut commands: Commands,
+    time: Res<FixedTime>,
+    mut explosion_events: EventWriter<ExplosionEvent>,
+    mut bullet_query: Query<(Entity, &Transform, &Velocity, &Lifetime, &mut Bullet)>,
+) {
+    for (entity, transform, velocity, lifetime, mut bullet) in bullet_query.iter_mut() {
+        bullet.travelled += velocity.linvel.length() * time.delta_seconds();
+        // the lifetime plugin already despawns bullets that are too old
+        let expired = lifetime.0.finished();
+        // bullets that got stuck are removed as well
+        let stuck = velocity.linvel.length_squared() < 80.;
+        if !expired && !stuck && !bullet.is_out_of_range() {
+            continue;
+        }
+        if let ProjectileBehaviour::Explosive {
+            radius,
+            damage,
+            knockback,
+        } = bullet.behaviour
+        {
+            explosion_events.send(ExplosionEvent {
+                source: entity,
+                position: transform.translation.truncate(),
+                radius,
+                damage,
+                knockback,
+            });
+        }
+        if !expired {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+/// Short lived circle shown where an explosion happened
+#[derive(Component)]
+pub struct ExplosionFlash;
+
+pub fn spawn_explosion_flash(commands: &mut Commands, position: Vec2, radius: f32) {
+    commands
+        .spawn_bundle(GeometryBuilder::build_as(
+            &shapes::Circle {
+                radius,
+                center: Vec2::ZERO,
+            },
+            DrawMode::Fill(FillMode::color(Color::rgba(1., 0.6, 0.1, 0.6))),
+            Transform::from_translation(position.extend(2.)),
+        ))
+        .insert(ExplosionFlash)
+        .insert(Lifetime::from_seconds(0.25))
+        .insert(RunEntity);
+}
+
+fn fade_explosion_flashes(
+    mut flash_query: Query<(&Lifetime, &mut DrawMode), With<ExplosionFlash>>,
+) {
+    for (lifetime, mut draw_mode) in flash_query.iter_mut() {
+        if let DrawMode::Fill(fill_mode) = draw_mode.as_mut() {
+            fill_mode.color.set_a(0.6 * lifetime.0.percent_left());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(inventory: &Inventory) -> Vec<WeaponKind> {
+        inventory
+            .weapons()
+            .iter()
+            .map(|weapon| weapon.kind)
+            .collect()
+    }
+
+    #[test]
+    fn collecting_a_new_weapon_keeps_the_slot_order_and_selects_it() {
+        let mut inventory = Inventory::new(Weapon::new(WeaponKind::Pistol));
+        inventory.collect(WeaponKind::Rifle);
+        assert_eq!(inventory.current().kind, WeaponKind::Rifle);
+        inventory.collect(WeaponKind::Shotgun);
+        assert_eq!(
+            kinds(&inventory),
+            vec![WeaponKind::Pistol, WeaponKind::Shotgun, WeaponKind::Rifle]
+        );
+        assert_eq!(inventory.current().kind, WeaponKind::Shotgun);
+    }
+
+    #[test]
+    fn collecting_an_owned_weapon_adds_two_magazines_of_ammo() {
+        let mut inventory = Inventory::new(Weapon::new(WeaponKind::Pistol));
+        inventory.collect(WeaponKind::Smg);
+        inventory.select(WeaponSelection::Slot(WeaponKind::Pistol.slot()));
+        inventory.collect(WeaponKind::Smg);
+
+        assert_eq!(kinds(&inventory), vec![WeaponKind::Pistol, WeaponKind::Smg]);
+        // ammo doesn't switch weapons
+        assert_eq!(inventory.current().kind, WeaponKind::Pistol);
+        assert_eq!(inventory.weapons()[1].reserve, Some(120 + 2 * 30));
+
+        // unlimited ammo stays unlimited
+        inventory.collect(WeaponKind::Pistol);
+        assert_eq!(inventory.current().reserve, None);
+    }
+
+    #[test]
+    fn selecting_cycles_and_ignores_empty_slots() {
+        let mut inventory = Inventory::new(Weapon::new(WeaponKind::Pistol));
+        inventory.collect(WeaponKind::Shotgun);
+        inventory.collect(WeaponKind::Launcher);
+
+        inventory.select(WeaponSelection::Next);
+        assert_eq!(inventory.current().kind, WeaponKind::Pistol);
+        inventory.select(WeaponSelection::Previous);
+        assert_eq!(inventory.current().kind, WeaponKind::Launcher);
+        inventory.select(WeaponSelection::Slot(WeaponKind::Shotgun.slot()));
+        assert_eq!(inventory.current().kind, WeaponKind::Shotgun);
+        inventory.select(WeaponSelection::Slot(WeaponKind::Rifle.slot()));
+        assert_eq!(inventory.current().kind, WeaponKind::Shotgun);
+    }
+
+    #[test]
+    fn switching_weapons_interrupts_the_reload() {
+        let mut inventory = Inventory::new(Weapon::new(WeaponKind::Pistol));
+        inventory.collect(WeaponKind::Shotgun);
+        inventory.current_mut().ammo = 0;
+        inventory.current_mut().start_reload();
+        assert!(inventory.current().is_reloading());
+
+        inventory.select(WeaponSelection::Previous);
+        inventory.select(WeaponSelection::Next);
+        assert!(!inventory.current().is_reloading());
+        assert_eq!(inventory.current().ammo, 0);
+    }
+}
@@ -0,0 +1,216 @@
+use bevy::prelude::*;
+use getting_over_him::{
+    ActionSource, Actions, Bullet, GameState, HeadlessGamePlugin, Health, Playback, Player,
+    Recording, ReplaySettings, SeedMode, Zombie,
+};
+use std::time::Duration;
+
+/// Ticks per second of the fixed timestep
+const TICKS_PER_SECOND: usize = 60;
+
+/// Builds the headless app and updates it until the run has started
+fn start_run() -> App {
+    let mut app = App::new();
+    app.insert_resource(SeedMode::Fixed(7));
+    let mut app = start(app);
+    assert_eq!(
+        *app.world.resource::<ActionSource>(),
+        ActionSource::Scripted
+    );
+    app
+}
+
+/// Adds the game to the app and updates it until the run has started
+fn start(mut app: App) -> App {
+    app.add_plugin(HeadlessGamePlugin);
+    // the assets are loaded in the background
+    for _ in 0..1000 {
+        app.update();
+        if *app.world.resource::<State<GameState>>().current() == GameState::Playing
+            && player(&mut app).is_some()
+        {
+            return app;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    panic!("the run didn't start");
+}
+
+/// Runs `ticks` fixed ticks, the headless app runs one per update
+fn run_ticks(app: &mut App, ticks: usize) {
+    for _ in 0..ticks {
+        app.update();
+    }
+}
+
+/// Runs fixed ticks until `done` returns true, at most for `seconds` of game time
+fn run_until(app: &mut App, seconds: usize, mut done: impl FnMut(&mut App) -> bool) -> bool {
+    for _ in 0..seconds * TICKS_PER_SECOND {
+        app.update();
+        if done(app) {
+            return true;
+        }
+    }
+    false
+}
+
+fn player(app: &mut App) -> Option<Entity> {
+    app.world
+        .query_filtered::<Entity, With<Player>>()
+        .iter(&app.world)
+        .next()
+}
+
+fn player_position(app: &mut App) -> Vec2 {
+    app.world
+        .query_filtered::<&Transform, With<Player>>()
+        .single(&app.world)
+        .translation
+        .truncate()
+}
+
+fn zombies(app: &mut App) -> Vec<(Entity, Vec2)> {
+    app.world
+        .query_filtered::<(Entity, &Transform), With<Zombie>>()
+        .iter(&app.world)
+        .map(|(entity, transform)| (entity, transform.translation.truncate()))
+        .collect()
+}
+
+#[test]
+fn waves_spawn_zombies() {
+    let mut app = start_run();
+    assert!(zombies(&mut app).is_empty());
+
+    // the first wave starts after a short warmup and spawns a zombie every second
+    run_ticks(&mut app, 10 * TICKS_PER_SECOND);
+    assert!(zombies(&mut app).len() >= 2);
+}
+
+#[test]
+fn zombies_hurt_the_player_on_contact() {
+    let mut app = start_run();
+    let player = player(&mut app).unwrap();
+    let max = app.world.get::<Health>(player).unwrap().max;
+
+    // the player stands still until the zombies got to them
+    let hurt = run_until(&mut app, 60, |app| {
+        app.world.get::<Health>(player).unwrap().current < max
+    });
+    assert!(hurt, "no zombie reached the player");
+}
+
+#[test]
+fn bullets_hit_zombies_and_go_away() {
+    let mut app = start_run();
+    let close = run_until(&mut app, 60, |app| {
+        let position = player_position(app);
+        zombies(app)
+            .iter()
+            .any(|(_, zombie)| zombie.distance(position) < 200.)
+    });
+    assert!(close, "no zombie came close to the player");
+
+    let position = player_position(&mut app);
+    let (target, target_position) = zombies(&mut app)
+        .into_iter()
+        .min_by(|(_, a), (_, b)| a.distance(position).total_cmp(&b.distance(position)))
+        .unwrap();
+    let health = app.world.get::<Health>(target).unwrap().current;
+    {
+        let mut actions = app.world.resource_mut::<Actions>();
+        actions.aim_target = Some(target_position);
+        actions.aim_direction = Some((target_position - position).normalize());
+        actions.trigger_pressed = true;
+    }
+    run_ticks(&mut app, 1);
+    app.world.resource_mut::<Actions>().trigger_pressed = false;
+
+    let bullets: Vec<Entity> = app
+        .world
+        .query_filtered::<Entity, With<Bullet>>()
+        .iter(&app.world)
+        .collect();
+    assert!(!bullets.is_empty(), "the pistol didn't fire");
+
+    let gone = run_until(&mut app, 5, |app| {
+        bullets
+            .iter()
+            .all(|bullet| app.world.get_entity(*bullet).is_none())
+    });
+    assert!(gone, "the bullets neither hit nor expired");
+    let hit = app
+        .world
+        .get::<Health>(target)
+        .map_or(true, |target| target.current < health);
+    assert!(hit, "the bullets missed the zombie");
+}
+
+/// Everything compared between a run and its replay
+#[derive(Debug, PartialEq)]
+struct Snapshot {
+    player_position: Vec2,
+    player_health: u32,
+    zombies: Vec<(Entity, String, Vec2, u32)>,
+}
+
+fn snapshot(app: &mut App) -> Snapshot {
+    let (transform, health) = app
+        .world
+        .query_filtered::<(&Transform, &Health), With<Player>>()
+        .single(&app.world);
+    let (player_position, player_health) = (transform.translation.truncate(), health.current);
+    let mut zombies: Vec<_> = app
+        .world
+        .query::<(Entity, &Zombie, &Transform, &Health)>()
+        .iter(&app.world)
+        .map(|(entity, zombie, transform, health)| {
+            (
+                entity,
+                zombie.kind.clone(),
+                transform.translation.truncate(),
+                health.current,
+            )
+        })
+        .collect();
+    zombies.sort_by_key(|(entity, ..)| *entity);
+    Snapshot {
+        player_position,
+        player_health,
+        zombies,
+    }
+}
+
+#[test]
+fn replays_reproduce_the_run() {
+    let ticks = 20 * TICKS_PER_SECOND;
+    let mut app = App::new();
+    app.insert_resource(SeedMode::Fixed(11))
+        .insert_resource(ReplaySettings {
+            record_to: Some(std::env::temp_dir().join("getting_over_him_headless.replay")),
+            play_from: None,
+        });
+    let mut recorded = start(app);
+    for tick in 0..ticks {
+        let angle = tick as f32 / 90.;
+        let mut actions = recorded.world.resource_mut::<Actions>();
+        actions.player_movement = Some(Vec2::new(angle.cos(), angle.sin()));
+        actions.aim_direction = Some(Vec2::new(-angle.sin(), angle.cos()));
+        actions.trigger_pressed = tick % 40 < 20;
+        actions.dash_pressed = tick % 300 == 150;
+        recorded.update();
+    }
+    let replay = recorded.world.resource::<Recording>().0.clone();
+    let expected = snapshot(&mut recorded);
+    assert!(!expected.zombies.is_empty(), "no zombie spawned");
+
+    let mut app = App::new();
+    app.insert_resource(Playback::new(replay));
+    let mut replayed = start(app);
+    assert_eq!(
+        *replayed.world.resource::<ActionSource>(),
+        ActionSource::Replay
+    );
+    run_ticks(&mut replayed, ticks);
+    assert_eq!(snapshot(&mut replayed), expected);
+}